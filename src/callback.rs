@@ -0,0 +1,222 @@
+use crate::error::{Error, Result};
+use oauth2::url::Url;
+use oauth2::{AuthorizationCode, CsrfToken};
+use std::fmt;
+use std::io::{self, Write};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+
+/// How long to wait for the browser to come back before giving up
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(300);
+/// Requests larger than this are not OAuth callbacks
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+const SUCCESS_PAGE: &str = "<html><body><h1>Login complete</h1><p>You can close this window and return to the terminal.</p></body></html>";
+const FAILURE_PAGE: &str = "<html><body><h1>Login failed</h1><p>Check the terminal for details.</p></body></html>";
+
+/// Why an authorization redirect was rejected
+#[derive(Debug)]
+pub enum CallbackError {
+    /// The input isn't a URL at all
+    Malformed(String),
+    /// The provider denied or failed the authorization, e.g. because the user declined
+    Provider { error: String, description: Option<String> },
+    /// The `state` parameter doesn't match the one we sent, so the redirect isn't a response to our request
+    StateMismatch,
+    MissingCode,
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallbackError::Malformed(reason) => write!(f, "Invalid redirect URL: {}", reason),
+            CallbackError::Provider { error, description: Some(description) } => write!(f, "Authorization failed: {} ({})", description, error),
+            CallbackError::Provider { error, description: None } => write!(f, "Authorization failed: {}", error),
+            CallbackError::StateMismatch => write!(f, "The redirect's state parameter doesn't match this login attempt, please try again"),
+            CallbackError::MissingCode => write!(f, "The redirect URL doesn't contain an authorization code"),
+        }
+    }
+}
+
+impl std::error::Error for CallbackError {}
+
+impl From<CallbackError> for Error {
+    fn from(e: CallbackError) -> Self {
+        Error::Auth(e.to_string())
+    }
+}
+
+/// Extracts the authorization code from the redirect URL after checking its `state` against the one we sent.
+///
+/// The state is checked before anything else, error responses included (they carry it too, RFC 6749
+/// section 4.1.2.1), so a redirect that isn't a response to our request can't fail the login either.
+pub fn parse_callback(redirect_url: &str, expected_state: &CsrfToken) -> std::result::Result<AuthorizationCode, CallbackError> {
+    let url = Url::parse(redirect_url.trim()).map_err(|e| CallbackError::Malformed(e.to_string()))?;
+    let param = |name: &str| url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned());
+
+    if param("state").as_deref() != Some(expected_state.secret().as_str()) {
+        return Err(CallbackError::StateMismatch);
+    }
+    if let Some(error) = param("error") {
+        return Err(CallbackError::Provider { error, description: param("error_description") });
+    }
+    match param("code") {
+        Some(code) if !code.is_empty() => Ok(AuthorizationCode::new(code)),
+        _ => Err(CallbackError::MissingCode),
+    }
+}
+
+/// Waits for the provider to redirect back after authorization and returns the validated code.
+///
+/// If the redirect URI points at this machine, a one-shot HTTP listener receives the callback
+/// directly. Otherwise, or if the port can't be bound, the user is asked to paste the URL.
+pub async fn receive_authorization_code(redirect_uri: &str, expected_state: &CsrfToken) -> Result<AuthorizationCode> {
+    let redirect = Url::parse(redirect_uri)?;
+    let is_loopback = redirect.scheme() == "http"
+        && matches!(redirect.host_str(), Some("localhost") | Some("127.0.0.1"));
+
+    if is_loopback {
+        let port = redirect.port_or_known_default().unwrap_or(80);
+        match TcpListener::bind(("127.0.0.1", port)).await {
+            Ok(listener) => {
+                println!("Waiting for the browser to redirect to {} ...", redirect_uri);
+                return timeout(CALLBACK_TIMEOUT, listen(listener, &redirect, expected_state))
+                    .await
+                    .map_err(|_| Error::Auth("Timed out waiting for the authorization redirect".to_string()))?;
+            }
+            Err(e) => log::warn!("Could not listen on port {} ({}), falling back to pasting the URL", port, e),
+        }
+    }
+
+    Ok(parse_callback(&read_redirect_from_stdin()?, expected_state)?)
+}
+
+fn read_redirect_from_stdin() -> Result<String> {
+    print!("Enter the URL you were redirected to: ");
+    io::stdout().flush()?;
+    let mut input_url = String::new();
+    io::stdin().read_line(&mut input_url)?;
+    Ok(input_url.trim().to_string())
+}
+
+async fn listen(listener: TcpListener, redirect: &Url, expected_state: &CsrfToken) -> Result<AuthorizationCode> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let target = match read_request_target(&mut stream).await {
+            Ok(target) => target,
+            Err(e) => {
+                log::debug!("Ignoring malformed callback request: {}", e);
+                respond(&mut stream, "400 Bad Request", FAILURE_PAGE).await;
+                continue;
+            }
+        };
+
+        let url = redirect.join(&target)?;
+        // Browsers also ask for things like /favicon.ico, only the redirect path is the callback
+        if url.path() != redirect.path() {
+            respond(&mut stream, "404 Not Found", "").await;
+            continue;
+        }
+
+        let result = parse_callback(url.as_str(), expected_state);
+        respond(&mut stream, "200 OK", if result.is_ok() { SUCCESS_PAGE } else { FAILURE_PAGE }).await;
+        match result {
+            // Any page can send the browser here, keep waiting for the response to our request
+            Err(CallbackError::StateMismatch) => log::warn!("Ignoring a redirect that doesn't belong to this login attempt"),
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Reads the request head and returns the target of a `GET` request line.
+async fn read_request_target(stream: &mut TcpStream) -> Result<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+        if request.len() > MAX_REQUEST_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Request too large").into());
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split_whitespace();
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) if target.starts_with('/') => Ok(target.to_string()),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Not a GET request").into()),
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    // The browser closing the connection early doesn't affect the login
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(redirect_url: &str) -> std::result::Result<AuthorizationCode, CallbackError> {
+        parse_callback(redirect_url, &CsrfToken::new("expected".to_string()))
+    }
+
+    #[test]
+    fn accepts_code_with_matching_state() {
+        let code = parse("http://localhost:8080/callback?code=abc%2F123&state=expected").unwrap();
+        assert_eq!(code.secret(), "abc/123");
+        assert!(parse("  http://localhost:8080/callback?state=expected&code=abc\n").is_ok());
+    }
+
+    #[test]
+    fn rejects_other_states() {
+        assert!(matches!(parse("http://localhost:8080/?code=abc&state=other"), Err(CallbackError::StateMismatch)));
+        assert!(matches!(parse("http://localhost:8080/?code=abc"), Err(CallbackError::StateMismatch)));
+        assert!(matches!(parse("http://localhost:8080/?code=abc&state="), Err(CallbackError::StateMismatch)));
+    }
+
+    #[test]
+    fn provider_errors_need_matching_state() {
+        assert!(matches!(parse("http://localhost:8080/?error=access_denied"), Err(CallbackError::StateMismatch)));
+        assert!(matches!(parse("http://localhost:8080/?error=access_denied&state=other"), Err(CallbackError::StateMismatch)));
+    }
+
+    #[test]
+    fn reports_provider_errors() {
+        match parse("http://localhost:8080/?error=access_denied&error_description=User+declined&state=expected") {
+            Err(CallbackError::Provider { error, description }) => {
+                assert_eq!(error, "access_denied");
+                assert_eq!(description.as_deref(), Some("User declined"));
+            }
+            other => panic!("unexpected result {:?}", other.map(|code| code.secret().clone())),
+        }
+        match parse("http://localhost:8080/?error=server_error&state=expected") {
+            Err(CallbackError::Provider { error, description: None }) => assert_eq!(error, "server_error"),
+            other => panic!("unexpected result {:?}", other.map(|code| code.secret().clone())),
+        }
+    }
+
+    #[test]
+    fn rejects_missing_or_empty_code() {
+        assert!(matches!(parse("http://localhost:8080/?state=expected"), Err(CallbackError::MissingCode)));
+        assert!(matches!(parse("http://localhost:8080/?code=&state=expected"), Err(CallbackError::MissingCode)));
+    }
+
+    #[test]
+    fn rejects_input_that_is_not_a_url() {
+        for input in ["", "   ", "code=abc&state=expected", "/callback?code=abc&state=expected", "http://", "not a url at all ü"] {
+            assert!(matches!(parse(input), Err(CallbackError::Malformed(_))), "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+#[derive(Parser)]
+#[command(version, about = "Syncs your Tidal playlists to Spotify")]
+pub struct Cli {
+    /// Path to the configuration file
+    #[arg(short, long, global = true, default_value = "config.toml")]
+    pub config: String,
+
+    /// Increase log output, can be repeated (-v, -vv, -vvv)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only show errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Only work on these Tidal playlists, by name or id. Can be repeated
+    #[arg(short, long = "playlist", global = true, value_name = "PLAYLIST")]
+    pub playlists: Vec<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Sync Tidal playlists to Spotify (the default)
+    Sync {
+        /// Show the changes that would be made without touching Spotify
+        #[arg(long)]
+        dry_run: bool,
+        /// Also sync playlists that haven't changed on Tidal since their last sync
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show the changes a sync would make, without touching Spotify
+    Diff {
+        /// Also include playlists that haven't changed on Tidal since their last sync
+        #[arg(long)]
+        force: bool,
+    },
+    /// Write the changes a sync would make to a plan file for review
+    Plan {
+        #[arg(default_value = "plan.json")]
+        path: String,
+        /// Also plan playlists that haven't changed on Tidal since their last sync
+        #[arg(long)]
+        force: bool,
+    },
+    /// Apply a plan file written by `plan`
+    Apply { path: String },
+    /// Log in to Tidal and Spotify, or manage the stored logins
+    Auth(AuthArgs),
+    /// List Tidal playlists and the Spotify playlists they are synced to
+    List,
+    /// Export Tidal playlists with their matched Spotify tracks as JSON
+    Export {
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Show what the local sync database knows about
+    Status,
+}
+
+#[derive(Args)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct AuthArgs {
+    /// Only log in to this service
+    #[arg(value_enum)]
+    pub service: Option<Service>,
+
+    /// Log in to Tidal by entering a code on another device, for machines without a browser
+    #[arg(long)]
+    pub device: bool,
+
+    #[command(subcommand)]
+    pub command: Option<AuthCommand>,
+}
+
+#[derive(Subcommand)]
+pub enum AuthCommand {
+    /// Print the login URLs, to be opened in a browser on any machine
+    Start {
+        /// Only log in to this service
+        #[arg(value_enum)]
+        service: Option<Service>,
+    },
+    /// Complete a login with the URL the browser was redirected to
+    Finish { redirect_url: String },
+    /// Show who is logged in and the scopes and expiry of the stored tokens
+    Status,
+    /// Refresh the access tokens now
+    Refresh {
+        /// Only refresh this service
+        #[arg(value_enum)]
+        service: Option<Service>,
+    },
+    /// Revoke the tokens where supported and delete them
+    Logout {
+        /// Only log out of this service
+        #[arg(value_enum)]
+        service: Option<Service>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    Tidal,
+    Spotify,
+}
+
+impl Cli {
+    pub fn log_level(&self) -> log::LevelFilter {
+        if self.quiet {
+            return log::LevelFilter::Error;
+        }
+        match self.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::cli::Service;
+use crate::config::{Config, OAuthConfig};
+use crate::db::{MatchMethod, SyncDatabase};
+use crate::error::{Error, Result};
+use crate::http::HttpClient;
+use crate::matcher;
+use crate::oauth::OAuthProvider;
+use crate::session::AuthSession;
+use crate::spotify::{self, SpotifyClient};
+use crate::sync::fetch_selected_playlists;
+use crate::tidal::data::fetch_playlist_tracks;
+use crate::tidal::{self, TidalClient};
+use crate::token_store::{StoredToken, TokenStore};
+use crate::utils::{format_age, format_relative};
+use serde_json::json;
+use std::fs;
+
+/// Prints the Tidal playlists and where each of them is synced to.
+pub async fn list_playlists(tidal_client: &TidalClient, selected: &[String]) -> Result<()> {
+    let db = SyncDatabase::open()?;
+    for playlist in fetch_selected_playlists(tidal_client, selected).await? {
+        let items = playlist.number_of_items.map(|count| format!("{} tracks", count)).unwrap_or_else(|| "unknown size".to_string());
+        match db.get_playlist(&playlist.id) {
+            Some(link) => println!(
+                "{} [{}] ({}) -> Spotify playlist {}, synced {}",
+                playlist.name, playlist.id, items, link.spotify_playlist_id, format_age(link.synced_at)
+            ),
+            None => println!("{} [{}] ({}) -> not synced", playlist.name, playlist.id, items),
+        }
+    }
+    Ok(())
+}
+
+/// Exports the Tidal playlists with their tracks and, where known, the Spotify track they map to.
+pub async fn export_playlists(tidal_client: &TidalClient, selected: &[String], output: Option<&str>) -> Result<()> {
+    let db = SyncDatabase::open()?;
+    let mut exported = Vec::new();
+    for mut playlist in fetch_selected_playlists(tidal_client, selected).await? {
+        fetch_playlist_tracks(tidal_client, &mut playlist).await?;
+        let tracks: Vec<_> = playlist
+            .tracks
+            .iter()
+            .map(|track| {
+                json!({
+                    "tidal_id": track.id,
+                    "title": track.attributes.title,
+                    "artists": track.artists,
+                    "album": track.album,
+                    "isrc": track.attributes.isrc,
+                    "spotify_uri": db.get_track(&track.id).map(|mapping| &mapping.spotify_uri),
+                })
+            })
+            .collect();
+        exported.push(json!({
+            "id": playlist.id,
+            "name": playlist.name,
+            "spotify_playlist_id": db.get_playlist(&playlist.id).map(|link| &link.spotify_playlist_id),
+            "tracks": tracks,
+        }));
+    }
+
+    let contents = serde_json::to_string_pretty(&exported)?;
+    match output {
+        Some(path) => {
+            fs::write(path, contents)?;
+            println!("Exported {} playlists to {}", exported.len(), path);
+        }
+        None => println!("{}", contents),
+    }
+    Ok(())
+}
+
+/// Prints a summary of the local sync database.
+pub fn print_status() -> Result<()> {
+    let db = SyncDatabase::open()?;
+
+    let mut links: Vec<_> = db.playlists().collect();
+    links.sort_by(|a, b| a.name.cmp(&b.name));
+    println!("Synced playlists: {}", links.len());
+    for link in links {
+        println!(
+            "  {} -> Spotify playlist {}, synced {}",
+            if link.name.is_empty() { &link.tidal_playlist_id } else { &link.name },
+            link.spotify_playlist_id,
+            format_age(link.synced_at)
+        );
+    }
+
+    let tracks: Vec<_> = db.tracks().collect();
+    let fuzzy = tracks.iter().filter(|track| track.match_method == MatchMethod::Fuzzy).count();
+    let low_confidence = tracks
+        .iter()
+        .filter(|track| track.confidence.is_some_and(|confidence| confidence < matcher::LOW_CONFIDENCE))
+        .count();
+    println!("Matched tracks: {} ({} by ISRC, {} by metadata, {} with low confidence)", tracks.len(), tracks.len() - fuzzy, fuzzy, low_confidence);
+    Ok(())
+}
+
+/// Prints the login URL of each selected service for `auth finish` to complete later.
+pub fn start_login(config: &Config, service: Option<Service>) -> Result<()> {
+    if service != Some(Service::Spotify) {
+        println!("Open this URL in a browser to log in to Tidal:\n{}\n", tidal::auth::start_login(config)?);
+    }
+    if service != Some(Service::Tidal) {
+        println!("Open this URL in a browser to log in to Spotify:\n{}\n", spotify::auth::start_login(config)?);
+    }
+    println!("Then run `auth finish <URL>` with the URL the browser was redirected to, for each service.");
+    Ok(())
+}
+
+/// Completes whichever pending login the redirect URL belongs to.
+pub async fn finish_login(config: &Config, redirect_url: &str) -> Result<()> {
+    if tidal::auth::finish_login(config, redirect_url).await? {
+        println!("Logged in to Tidal");
+    } else if spotify::auth::finish_login(config, redirect_url).await? {
+        println!("Logged in to Spotify");
+    } else {
+        return Err(Error::Auth("The redirect URL doesn't belong to a pending login, run `auth start` first".to_string()));
+    }
+    Ok(())
+}
+
+/// The selected services with their OAuth provider and credentials.
+fn providers(config: &Config, service: Option<Service>) -> Vec<(Service, &'static OAuthProvider, &OAuthConfig)> {
+    let mut providers = Vec::new();
+    if service != Some(Service::Spotify) {
+        providers.push((Service::Tidal, &tidal::auth::TIDAL, &config.tidal));
+    }
+    if service != Some(Service::Tidal) {
+        providers.push((Service::Spotify, &spotify::auth::SPOTIFY, &config.spotify));
+    }
+    providers
+}
+
+fn describe_expiry(token: &StoredToken) -> String {
+    match token.expires_at {
+        Some(expires_at) if token.is_expired() => format!("expired {}", format_relative(expires_at)),
+        Some(expires_at) => format!("expires {}", format_relative(expires_at)),
+        None => "has no known expiry".to_string(),
+    }
+}
+
+/// Prints who is logged in to each service and what the stored tokens allow.
+pub async fn print_auth_status(config: &Config) -> Result<()> {
+    let store = TokenStore::open(&config.tokens)?;
+    for (service, provider, oauth_config) in providers(config, None) {
+        let Some(token) = store.load(provider.token_key, provider.legacy_token_file)? else {
+            println!("{}: not logged in", provider.name);
+            continue;
+        };
+
+        // Looking up the user may refresh the tokens, so show the ones stored afterwards
+        let session = AuthSession::new(provider, oauth_config.clone(), store.clone(), token.clone(), HttpClient::new(&config.http)?);
+        let user = match service {
+            Service::Tidal => tidal::data::fetch_user_name(&TidalClient::new(session)).await,
+            Service::Spotify => spotify::data::get_current_user(&SpotifyClient::new(session))
+                .await
+                .map(|user| format!("{} ({})", user.display_name, user.id)),
+        };
+        let token = store.load(provider.token_key, provider.legacy_token_file)?.unwrap_or(token);
+
+        match user {
+            Ok(user) => println!("{}: logged in as {}", provider.name, user),
+            Err(e) => println!("{}: logged in, but looking up the user failed: {}", provider.name, e),
+        }
+        println!("  Scopes: {}", if token.scopes.is_empty() { "unknown".to_string() } else { token.scopes.join(" ") });
+        println!("  Access token {}, obtained {}", describe_expiry(&token), format_age(token.obtained_at));
+        println!("  Refresh token: {}", if token.refresh_token.is_some() { "stored" } else { "none" });
+    }
+    Ok(())
+}
+
+/// Exchanges the refresh token for a new access token now, rather than when the current one expires.
+pub async fn refresh_login(config: &Config, service: Option<Service>) -> Result<()> {
+    let store = TokenStore::open(&config.tokens)?;
+    let http = HttpClient::new(&config.http)?;
+    for (_, provider, oauth_config) in providers(config, service) {
+        let Some(token) = store.load(provider.token_key, provider.legacy_token_file)? else {
+            println!("{}: not logged in", provider.name);
+            continue;
+        };
+        let refresh_token = token
+            .refresh_token
+            .ok_or_else(|| Error::Auth(format!("The {} login can't be refreshed, run `auth` to log in again", provider.name)))?;
+        let token = provider.refresh_access_token(&refresh_token, oauth_config, &store, &http).await?;
+        println!("{}: refreshed, the access token {}", provider.name, describe_expiry(&token));
+    }
+    Ok(())
+}
+
+/// Revokes the tokens where the provider supports it, and deletes them along with any pending login.
+pub async fn logout(config: &Config, service: Option<Service>) -> Result<()> {
+    let store = TokenStore::open(&config.tokens)?;
+    let http = HttpClient::new(&config.http)?;
+    for (_, provider, oauth_config) in providers(config, service) {
+        if let Some(token) = store.load(provider.token_key, provider.legacy_token_file)? {
+            match provider.revoke(oauth_config, &token, &http).await {
+                Ok(true) => println!("{}: revoked the tokens", provider.name),
+                Ok(false) => println!("{}: tokens can't be revoked, remove the app's access in your account settings to invalidate them", provider.name),
+                // The local tokens are deleted anyway, a failed revocation only means they stay valid until they expire
+                Err(e) => log::warn!("Could not revoke the {} tokens: {}", provider.name, e),
+            }
+        }
+        store.remove(provider.token_key)?;
+        store.remove_pending(provider.token_key)?;
+        println!("{}: logged out", provider.name);
+    }
+    Ok(())
+}
@@ -1,56 +1,118 @@
+use crate::error::{Error, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
-use toml;
 
 #[derive(Deserialize, Serialize)]
 pub struct Config {
-    pub tidal: TidalConfig,
-    pub spotify: SpotifyConfig,
+    pub tidal: OAuthConfig,
+    pub spotify: OAuthConfig,
+    #[serde(default)]
+    pub sync: SyncConfig,
+    #[serde(default)]
+    pub tokens: TokenConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct TidalConfig {
+/// Client credentials registered with a service's developer portal
+#[derive(Deserialize, Serialize, Clone)]
+pub struct OAuthConfig {
     pub client_id: String,
     pub client_secret: String,
     pub redirect_uri: String,
 }
 
+#[derive(Deserialize, Serialize, Default)]
+pub struct TokenConfig {
+    /// Encrypt stored tokens with a passphrase, taken from TIDAL_SPOTIFY_SYNC_PASSPHRASE or asked for
+    #[serde(default)]
+    pub encrypt: bool,
+}
+
 #[derive(Deserialize, Serialize)]
-pub struct SpotifyConfig {
-    pub client_id: String,
-    pub client_secret: String,
-    pub redirect_uri: String,
+pub struct HttpConfig {
+    /// How many times a failed request is retried before giving up
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self { max_retries: default_max_retries() }
+    }
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct SyncConfig {
+    #[serde(default)]
+    pub mode: SyncMode,
+    /// Per-playlist overrides, keyed by Tidal playlist id or name
+    #[serde(default)]
+    pub playlists: HashMap<String, PlaylistConfig>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PlaylistConfig {
+    pub mode: SyncMode,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// Only ever add tracks to the Spotify playlist
+    #[default]
+    Append,
+    /// Also remove tracks that are no longer in the Tidal playlist
+    Mirror,
 }
 
-pub fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
-    let config_path = "config.toml";
+impl SyncConfig {
+    pub fn mode_for(&self, playlist_id: &str, playlist_name: &str) -> SyncMode {
+        self.playlists
+            .get(playlist_id)
+            .or_else(|| self.playlists.get(playlist_name))
+            .map(|playlist| playlist.mode)
+            .unwrap_or(self.mode)
+    }
+}
 
+pub fn load_config(config_path: &str) -> Result<Config> {
     if !std::path::Path::new(config_path).exists() {
         let default_config = Config {
-            tidal: TidalConfig {
+            tidal: OAuthConfig {
                 client_id: "your_tidal_client_id".to_string(),
                 client_secret: "your_tidal_client_secret".to_string(),
                 redirect_uri: "http://localhost:8080".to_string(),
             },
-            spotify: SpotifyConfig {
+            spotify: OAuthConfig {
                 client_id: "your_spotify_client_id".to_string(),
                 client_secret: "your_spotify_client_secret".to_string(),
                 redirect_uri: "http://localhost:8080".to_string(),
             },
+            sync: SyncConfig::default(),
+            tokens: TokenConfig::default(),
+            http: HttpConfig::default(),
         };
 
-        let toml_string = toml::to_string_pretty(&default_config)?;
+        let toml_string = toml::to_string_pretty(&default_config).map_err(|e| Error::Config(e.to_string()))?;
 
         let mut file = fs::File::create(config_path)?;
         file.write_all(toml_string.as_bytes())?;
 
-        return Err("Configuration file not found. A default 'config.toml' has been created. Please update it with your credentials.".to_string()
-            .into());
+        return Err(Error::Config(format!(
+            "Configuration file not found. A default '{}' has been created. Please update it with your credentials.",
+            config_path
+        )));
     }
 
     // Read and parse the existing config file
     let config_str = fs::read_to_string(config_path)?;
-    let config: Config = toml::from_str(&config_str)?;
+    let config: Config = toml::from_str(&config_str).map_err(|e| Error::Config(format!("{}: {}", config_path, e)))?;
     Ok(config)
 }
@@ -0,0 +1,169 @@
+use crate::error::Result;
+use crate::report::{PlaylistReport, UnmatchedTrack};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DB_PATH: &str = "sync_db.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMethod {
+    Isrc,
+    /// Title, artist and duration comparison after the ISRC search found nothing
+    Fuzzy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrackMapping {
+    pub tidal_id: String,
+    pub isrc: String,
+    pub spotify_uri: String,
+    pub match_method: MatchMethod,
+    /// Matcher confidence in `0.0..=1.0`, only recorded for fuzzy matches
+    #[serde(default)]
+    pub confidence: Option<f64>,
+    pub matched_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaylistLink {
+    pub tidal_playlist_id: String,
+    #[serde(default)]
+    pub name: String,
+    pub spotify_playlist_id: String,
+    pub synced_at: u64,
+    /// Tidal `lastModifiedAt` of the playlist as of the last completed sync.
+    #[serde(default)]
+    pub last_modified_at: Option<String>,
+    #[serde(default)]
+    pub number_of_items: Option<u32>,
+}
+
+/// Single-file store of everything we remember between sync runs, keyed by Tidal ids.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SyncDatabase {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    tracks: HashMap<String, TrackMapping>,
+    #[serde(default)]
+    playlists: HashMap<String, PlaylistLink>,
+    /// Tracks that couldn't be matched when each playlist was last synced, keyed by Tidal playlist id
+    #[serde(default)]
+    unmatched: HashMap<String, PlaylistReport>,
+}
+
+impl SyncDatabase {
+    pub fn open() -> Result<Self> {
+        Self::open_at(DB_PATH)
+    }
+
+    pub fn open_at<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut db = if path.exists() {
+            let contents = fs::read_to_string(&path)?;
+            serde_json::from_str::<SyncDatabase>(&contents)?
+        } else {
+            SyncDatabase::default()
+        };
+        db.path = path;
+        Ok(db)
+    }
+
+    pub fn get_track(&self, tidal_id: &str) -> Option<&TrackMapping> {
+        self.tracks.get(tidal_id)
+    }
+
+    pub fn insert_track(&mut self, tidal_id: &str, isrc: &str, spotify_uri: &str, match_method: MatchMethod, confidence: Option<f64>) {
+        let mapping = TrackMapping {
+            tidal_id: tidal_id.to_string(),
+            isrc: isrc.to_string(),
+            spotify_uri: spotify_uri.to_string(),
+            match_method,
+            confidence,
+            matched_at: now(),
+        };
+        self.tracks.insert(tidal_id.to_string(), mapping);
+    }
+
+    pub fn get_playlist(&self, tidal_playlist_id: &str) -> Option<&PlaylistLink> {
+        self.playlists.get(tidal_playlist_id)
+    }
+
+    pub fn link_playlist(&mut self, tidal_playlist_id: &str, name: &str, spotify_playlist_id: &str) {
+        let link = PlaylistLink {
+            tidal_playlist_id: tidal_playlist_id.to_string(),
+            name: name.to_string(),
+            spotify_playlist_id: spotify_playlist_id.to_string(),
+            synced_at: now(),
+            last_modified_at: None,
+            number_of_items: None,
+        };
+        self.playlists.insert(tidal_playlist_id.to_string(), link);
+    }
+
+    pub fn unlink_playlist(&mut self, tidal_playlist_id: &str) {
+        self.playlists.remove(tidal_playlist_id);
+    }
+
+    pub fn mark_playlist_synced(&mut self, tidal_playlist_id: &str, last_modified_at: Option<&str>, number_of_items: Option<u32>) {
+        if let Some(link) = self.playlists.get_mut(tidal_playlist_id) {
+            link.synced_at = now();
+            link.last_modified_at = last_modified_at.map(str::to_string);
+            link.number_of_items = number_of_items;
+        }
+    }
+
+    /// A playlist is only considered unchanged if Tidal reported a modification date and it matches the last sync.
+    pub fn is_playlist_unchanged(&self, tidal_playlist_id: &str, last_modified_at: Option<&str>, number_of_items: Option<u32>) -> bool {
+        match (self.playlists.get(tidal_playlist_id), last_modified_at) {
+            (Some(link), Some(last_modified_at)) => {
+                link.last_modified_at.as_deref() == Some(last_modified_at) && link.number_of_items == number_of_items
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces the unmatched tracks recorded for the playlist, forgetting it once all of them matched.
+    pub fn set_unmatched_tracks(&mut self, tidal_playlist_id: &str, name: &str, unmatched: Vec<UnmatchedTrack>) {
+        if unmatched.is_empty() {
+            self.unmatched.remove(tidal_playlist_id);
+            return;
+        }
+        let report = PlaylistReport { tidal_playlist_id: tidal_playlist_id.to_string(), name: name.to_string(), unmatched };
+        self.unmatched.insert(tidal_playlist_id.to_string(), report);
+    }
+
+    /// Forgets the unmatched tracks of playlists for which `keep` returns false, e.g. ones deleted on Tidal.
+    pub fn retain_unmatched_tracks(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.unmatched.retain(|tidal_playlist_id, _| keep(tidal_playlist_id));
+    }
+
+    pub fn unmatched_tracks(&self) -> impl Iterator<Item = &PlaylistReport> {
+        self.unmatched.values()
+    }
+
+    pub fn playlists(&self) -> impl Iterator<Item = &PlaylistLink> {
+        self.playlists.values()
+    }
+
+    pub fn tracks(&self) -> impl Iterator<Item = &TrackMapping> {
+        self.tracks.values()
+    }
+
+    /// Writes the database to a temporary file first so an interrupted run never leaves it truncated.
+    pub fn save(&self) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
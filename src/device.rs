@@ -0,0 +1,109 @@
+use crate::config::OAuthConfig;
+use crate::error::{check_status, Error, Result};
+use crate::http::HttpClient;
+use crate::oauth::OAuthProvider;
+use oauth2::basic::BasicTokenResponse;
+use serde::Deserialize;
+use tokio::time::{sleep, Duration, Instant};
+
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// How much to slow down when the provider asks us to, as prescribed by RFC 8628
+const SLOW_DOWN_INCREMENT: Duration = Duration::from_secs(5);
+/// Upper bound for backing off after network or server errors
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Response of the device authorization endpoint. Tidal uses camelCase names instead of the RFC 8628 ones.
+#[derive(Deserialize)]
+struct DeviceAuthorization {
+    #[serde(alias = "deviceCode")]
+    device_code: String,
+    #[serde(alias = "userCode")]
+    user_code: String,
+    #[serde(alias = "verificationUri")]
+    verification_uri: String,
+    #[serde(alias = "verificationUriComplete", default)]
+    verification_uri_complete: Option<String>,
+    #[serde(alias = "expiresIn")]
+    expires_in: u64,
+    #[serde(default = "default_interval")]
+    interval: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Runs the device authorization flow: prints a code for the user to enter on another device and
+/// polls the token endpoint until they did, declined, or the code expired.
+pub async fn authorize_device(
+    provider: &OAuthProvider,
+    device_authorization_url: &str,
+    config: &OAuthConfig,
+    http: &HttpClient,
+) -> Result<BasicTokenResponse> {
+    let scope = provider.scopes.join(" ");
+    let request = http
+        .token_post(device_authorization_url)
+        .form(&[("client_id", config.client_id.as_str()), ("scope", scope.as_str())]);
+    let response = http.send(request).await?;
+    let authorization: DeviceAuthorization = check_status(response).await?.json().await?;
+
+    println!("To log in to {}, open {} and enter the code {}", provider.name, authorization.verification_uri, authorization.user_code);
+    if let Some(complete_uri) = &authorization.verification_uri_complete {
+        println!("or open {} directly", complete_uri);
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(authorization.expires_in);
+    let mut interval = Duration::from_secs(authorization.interval.max(1));
+    loop {
+        sleep(interval).await;
+        if Instant::now() >= deadline {
+            return Err(Error::Auth("The device code expired before the login was completed, please try again".to_string()));
+        }
+
+        let request = http
+            .token_post(provider.token_url)
+            .basic_auth(&config.client_id, Some(&config.client_secret))
+            .form(&[
+                ("grant_type", DEVICE_CODE_GRANT),
+                ("device_code", authorization.device_code.as_str()),
+                ("client_id", config.client_id.as_str()),
+                ("scope", scope.as_str()),
+            ]);
+        let result = http.send(request).await;
+        let response = match result {
+            Ok(response) if !response.status().is_server_error() => response,
+            Ok(response) => {
+                log::warn!("Polling for the {} login failed with {}, backing off", provider.name, response.status());
+                interval = (interval * 2).min(MAX_POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Polling for the {} login failed ({}), backing off", provider.name, e);
+                interval = (interval * 2).min(MAX_POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        if response.status().is_success() {
+            return Ok(response.json().await?);
+        }
+        let error: TokenError = response.json().await?;
+        match error.error.as_str() {
+            "authorization_pending" => log::debug!("Waiting for the {} login to be completed", provider.name),
+            "slow_down" => interval += SLOW_DOWN_INCREMENT,
+            "expired_token" => return Err(Error::Auth("The device code expired before the login was completed, please try again".to_string())),
+            "access_denied" => return Err(Error::Auth(format!("The {} login was declined", provider.name))),
+            _ => {
+                let description = error.error_description.unwrap_or_default();
+                return Err(Error::Auth(format!("{} device login failed: {} {}", provider.name, error.error, description)));
+            }
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use crate::config::SyncMode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// A single modification of a Spotify playlist, applied in order against the playlist as left by the previous one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PlaylistChange {
+    /// Remove every occurrence of the given URIs
+    Remove { uris: Vec<String> },
+    /// Insert the URIs, in order, before the item currently at `position`
+    Add { uris: Vec<String>, position: usize },
+    /// Move the item at `range_start` in front of the item currently at `insert_before`
+    Move { range_start: usize, insert_before: usize },
+}
+
+/// Computes the changes that turn `current` into `desired`.
+///
+/// Tracks only present on Spotify are kept in append mode and removed in mirror mode. Items without
+/// a URI can't be matched or removed, they are left where they are so positions stay in line with
+/// Spotify's. Reordering leaves the longest run of tracks already in the right relative order in
+/// place, so the number of moves is minimal.
+pub fn diff_playlist(current: &[Option<String>], desired: &[String], mode: SyncMode) -> Vec<PlaylistChange> {
+    let mut changes = Vec::new();
+    let mut current = current.to_vec();
+
+    let mut seen = HashSet::new();
+    let desired: Vec<&String> = desired.iter().filter(|uri| seen.insert(*uri)).collect();
+
+    if mode == SyncMode::Mirror {
+        // Spotify only removes by URI, so duplicated tracks are removed entirely and added back once
+        let mut stale: Vec<String> = Vec::new();
+        for (index, uri) in current.iter().enumerate() {
+            let Some(uri) = uri else { continue };
+            let duplicated = current[..index].iter().any(|other| other.as_ref() == Some(uri));
+            if (!seen.contains(uri) || duplicated) && !stale.contains(uri) {
+                stale.push(uri.clone());
+            }
+        }
+        if !stale.is_empty() {
+            current.retain(|uri| uri.as_ref().is_none_or(|uri| !stale.contains(uri)));
+            changes.push(PlaylistChange::Remove { uris: stale });
+        }
+    }
+
+    // One copy of every wanted track is put in order, the first one so moves are only needed when it
+    // is out of order. Other copies in append mode are left where they are, and may end up before it.
+    let mut matched = HashSet::new();
+    let mut current: Vec<Slot> = current
+        .into_iter()
+        .map(|uri| {
+            let is_match = uri.as_ref().is_some_and(|uri| seen.contains(uri) && matched.insert(uri.clone()));
+            Slot { uri, matched: is_match }
+        })
+        .collect();
+
+    // Insert missing tracks right after the track preceding them in Tidal, batching consecutive ones
+    let mut pending: Vec<String> = Vec::new();
+    let mut pending_position = 0;
+    let mut previous: Option<&String> = None;
+    for uri in &desired {
+        if matched.contains(*uri) {
+            flush_additions(&mut changes, &mut current, &mut pending, pending_position);
+        } else {
+            if pending.is_empty() {
+                pending_position = previous.map(|previous| position_of(&current, previous) + 1).unwrap_or(0);
+            }
+            pending.push((*uri).clone());
+        }
+        previous = Some(uri);
+    }
+    flush_additions(&mut changes, &mut current, &mut pending, pending_position);
+
+    // Desired index of every track in its current position, skipping tracks Tidal doesn't know about
+    let desired_index: std::collections::HashMap<&String, usize> = desired.iter().enumerate().map(|(index, uri)| (*uri, index)).collect();
+    let current_order: Vec<usize> = current
+        .iter()
+        .filter(|slot| slot.matched)
+        .filter_map(|slot| slot.uri.as_ref())
+        .map(|uri| desired_index[uri])
+        .collect();
+    let in_order: HashSet<usize> = longest_increasing_subsequence(&current_order).into_iter().collect();
+
+    for (index, uri) in desired.iter().enumerate() {
+        if in_order.contains(&index) {
+            continue;
+        }
+        let from = position_of(&current, uri);
+        let target = if index == 0 { 0 } else { position_of(&current, desired[index - 1]) + 1 };
+        if from == target || from + 1 == target {
+            continue;
+        }
+
+        let track = current.remove(from);
+        current.insert(if target > from { target - 1 } else { target }, track);
+        changes.push(PlaylistChange::Move { range_start: from, insert_before: target });
+    }
+
+    changes
+}
+
+struct Slot {
+    uri: Option<String>,
+    matched: bool,
+}
+
+fn flush_additions(changes: &mut Vec<PlaylistChange>, current: &mut Vec<Slot>, pending: &mut Vec<String>, position: usize) {
+    if pending.is_empty() {
+        return;
+    }
+    let uris = std::mem::take(pending);
+    current.splice(position..position, uris.iter().map(|uri| Slot { uri: Some(uri.clone()), matched: true }));
+    changes.push(PlaylistChange::Add { uris, position });
+}
+
+fn position_of(tracks: &[Slot], uri: &str) -> usize {
+    tracks
+        .iter()
+        .position(|slot| slot.matched && slot.uri.as_deref() == Some(uri))
+        .expect("track is present in the playlist")
+}
+
+/// Returns the values of one longest strictly increasing subsequence of `values`.
+fn longest_increasing_subsequence(values: &[usize]) -> Vec<usize> {
+    // tails[k] is the index of the smallest value ending an increasing run of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut predecessors = vec![None; values.len()];
+    for (index, value) in values.iter().enumerate() {
+        let length = tails.partition_point(|&tail| values[tail] < *value);
+        if length > 0 {
+            predecessors[index] = Some(tails[length - 1]);
+        }
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+
+    let mut subsequence = Vec::new();
+    let mut next = tails.last().copied();
+    while let Some(index) = next {
+        subsequence.push(values[index]);
+        next = predecessors[index];
+    }
+    subsequence.reverse();
+    subsequence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uris(tracks: &[&str]) -> Vec<String> {
+        tracks.iter().map(|track| track.to_string()).collect()
+    }
+
+    fn slots(tracks: &[&str]) -> Vec<Option<String>> {
+        tracks.iter().map(|track| Some(track.to_string())).collect()
+    }
+
+    /// Applies the changes the way Spotify does, panicking on positions outside the playlist.
+    fn apply(current: &[Option<String>], changes: &[PlaylistChange]) -> Vec<Option<String>> {
+        let mut playlist = current.to_vec();
+        for change in changes {
+            match change {
+                PlaylistChange::Remove { uris } => playlist.retain(|uri| uri.as_ref().is_none_or(|uri| !uris.contains(uri))),
+                PlaylistChange::Add { uris, position } => {
+                    assert!(*position <= playlist.len(), "adding at {} past the end", position);
+                    playlist.splice(*position..*position, uris.iter().cloned().map(Some));
+                }
+                PlaylistChange::Move { range_start, insert_before } => {
+                    assert!(*insert_before <= playlist.len(), "moving before {} past the end", insert_before);
+                    let track = playlist.remove(*range_start);
+                    let target = if insert_before > range_start { insert_before - 1 } else { *insert_before };
+                    playlist.insert(target, track);
+                }
+            }
+        }
+        playlist
+    }
+
+    fn assert_mirrored(current: &[Option<String>], desired: &[String]) -> Vec<PlaylistChange> {
+        let changes = diff_playlist(current, desired, SyncMode::Mirror);
+        let result: Vec<String> = apply(current, &changes).into_iter().flatten().collect();
+        let mut seen = HashSet::new();
+        let expected: Vec<String> = desired.iter().filter(|uri| seen.insert(*uri)).cloned().collect();
+        assert_eq!(result, expected, "changes: {:?}", changes);
+        changes
+    }
+
+    /// Checks that `desired` is a subsequence of `playlist`.
+    fn assert_in_order(playlist: &[String], desired: &[String]) {
+        let mut remaining = playlist.iter();
+        for uri in desired {
+            assert!(remaining.any(|other| other == uri), "{} is out of order in {:?}", uri, playlist);
+        }
+    }
+
+    fn count_moves(changes: &[PlaylistChange]) -> usize {
+        changes.iter().filter(|change| matches!(change, PlaylistChange::Move { .. })).count()
+    }
+
+    #[test]
+    fn playlist_in_sync_needs_no_changes() {
+        let tracks = uris(&["a", "b", "c"]);
+        assert!(diff_playlist(&slots(&["a", "b", "c"]), &tracks, SyncMode::Mirror).is_empty());
+        assert!(diff_playlist(&slots(&["a", "b", "c"]), &tracks, SyncMode::Append).is_empty());
+    }
+
+    #[test]
+    fn inserts_at_front_middle_and_end() {
+        let changes = assert_mirrored(&slots(&["b", "d"]), &uris(&["a", "b", "c", "d", "e"]));
+        assert_eq!(
+            changes,
+            vec![
+                PlaylistChange::Add { uris: uris(&["a"]), position: 0 },
+                PlaylistChange::Add { uris: uris(&["c"]), position: 2 },
+                PlaylistChange::Add { uris: uris(&["e"]), position: 4 },
+            ]
+        );
+    }
+
+    #[test]
+    fn batches_consecutive_inserts() {
+        let changes = assert_mirrored(&slots(&["a", "d"]), &uris(&["a", "b", "c", "d"]));
+        assert_eq!(changes, vec![PlaylistChange::Add { uris: uris(&["b", "c"]), position: 1 }]);
+    }
+
+    #[test]
+    fn fills_an_empty_playlist() {
+        assert_mirrored(&[], &uris(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn moves_a_single_track() {
+        let changes = assert_mirrored(&slots(&["a", "b", "c", "d"]), &uris(&["d", "a", "b", "c"]));
+        assert_eq!(changes, vec![PlaylistChange::Move { range_start: 3, insert_before: 0 }]);
+
+        let changes = assert_mirrored(&slots(&["a", "b", "c", "d"]), &uris(&["b", "c", "d", "a"]));
+        assert_eq!(count_moves(&changes), 1);
+    }
+
+    #[test]
+    fn reverses_with_minimal_moves() {
+        let tracks: Vec<String> = (0..10).map(|index| index.to_string()).collect();
+        let current: Vec<Option<String>> = tracks.iter().rev().cloned().map(Some).collect();
+        let changes = assert_mirrored(&current, &tracks);
+        assert_eq!(count_moves(&changes), tracks.len() - 1);
+    }
+
+    #[test]
+    fn reorders_while_inserting_and_removing() {
+        assert_mirrored(&slots(&["x", "c", "a", "y", "b"]), &uris(&["a", "new", "b", "c", "other"]));
+    }
+
+    #[test]
+    fn mirror_removes_duplicates_on_spotify() {
+        let changes = assert_mirrored(&slots(&["a", "b", "a", "c", "b"]), &uris(&["a", "b", "c"]));
+        assert_eq!(changes[0], PlaylistChange::Remove { uris: uris(&["a", "b"]) });
+    }
+
+    #[test]
+    fn ignores_duplicates_in_tidal() {
+        let changes = assert_mirrored(&slots(&["a", "c"]), &uris(&["a", "b", "a", "c", "b"]));
+        assert_eq!(changes, vec![PlaylistChange::Add { uris: uris(&["b"]), position: 1 }]);
+    }
+
+    #[test]
+    fn append_keeps_tracks_only_on_spotify() {
+        let current = slots(&["x", "c", "a", "y", "a"]);
+        let desired = uris(&["a", "b", "c"]);
+        let changes = diff_playlist(&current, &desired, SyncMode::Append);
+        assert!(!changes.iter().any(|change| matches!(change, PlaylistChange::Remove { .. })));
+
+        let result: Vec<String> = apply(&current, &changes).into_iter().flatten().collect();
+        let mut sorted = result.clone();
+        sorted.sort();
+        assert_eq!(sorted, uris(&["a", "a", "b", "c", "x", "y"]));
+
+        assert_in_order(&result, &desired);
+    }
+
+    #[test]
+    fn append_orders_one_copy_of_duplicated_tracks() {
+        let current = slots(&["5", "5", "3", "3", "6", "4"]);
+        let desired = uris(&["7", "3", "2", "0", "5"]);
+        let changes = diff_playlist(&current, &desired, SyncMode::Append);
+
+        let result: Vec<String> = apply(&current, &changes).into_iter().flatten().collect();
+        let mut sorted = result.clone();
+        sorted.sort();
+        assert_eq!(sorted, uris(&["0", "2", "3", "3", "4", "5", "5", "6", "7"]));
+        assert_in_order(&result, &desired);
+    }
+
+    #[test]
+    fn leaves_items_without_uri_in_place() {
+        let current = vec![None, Some("c".to_string()), Some("x".to_string()), None, Some("a".to_string())];
+        let desired = uris(&["a", "b", "c"]);
+        let changes = assert_mirrored(&current, &desired);
+        assert!(changes.contains(&PlaylistChange::Remove { uris: uris(&["x"]) }));
+
+        let result = apply(&current, &changes);
+        assert_eq!(result.len(), 5);
+        assert_eq!(result[0], None);
+        assert_eq!(result.iter().filter(|uri| uri.is_none()).count(), 2);
+    }
+
+    #[test]
+    fn random_playlists() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(6);
+        let random_tracks = |rng: &mut StdRng| -> Vec<String> {
+            let length = rng.gen_range(0..12);
+            (0..length).map(|_| rng.gen_range(0..10).to_string()).collect()
+        };
+        for _ in 0..10_000 {
+            let current: Vec<Option<String>> = random_tracks(&mut rng)
+                .into_iter()
+                .map(|uri| if uri == "9" { None } else { Some(uri) })
+                .collect();
+            let desired: Vec<String> = random_tracks(&mut rng).into_iter().filter(|uri| uri != "9").collect();
+            assert_mirrored(&current, &desired);
+
+            let changes = diff_playlist(&current, &desired, SyncMode::Append);
+            let result: Vec<String> = apply(&current, &changes).into_iter().flatten().collect();
+            let mut seen = HashSet::new();
+            let unique: Vec<String> = desired.iter().filter(|uri| seen.insert(*uri)).cloned().collect();
+            assert_in_order(&result, &unique);
+            // Nothing is removed in append mode
+            let before: Vec<String> = current.iter().flatten().cloned().collect();
+            let count = |tracks: &[String], uri: &String| tracks.iter().filter(|other| *other == uri).count();
+            assert!(before.iter().all(|uri| count(&result, uri) >= count(&before, uri)));
+        }
+    }
+}
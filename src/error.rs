@@ -0,0 +1,138 @@
+use oauth2::{ConfigurationError, ErrorResponse, RequestTokenError};
+use reqwest::{Response, StatusCode};
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong talking to Tidal and Spotify or handling local files.
+#[derive(Debug)]
+pub enum Error {
+    /// Logging in failed, or a service rejected the tokens even after refreshing them
+    Auth(String),
+    /// The service answered 429, `retry_after` is how long it asked us to wait
+    RateLimited { url: String, retry_after: Option<u64> },
+    NotFound { url: String },
+    /// Any other unsuccessful response
+    Http { status: StatusCode, url: String, body: String },
+    /// A response or file doesn't have the expected contents
+    Decode(String),
+    Config(String),
+    /// The request couldn't be sent or the response not received, e.g. because of a network error
+    Request(reqwest::Error),
+    Io(io::Error),
+    /// A Spotify playlist was changed after the sync plan for it was written
+    PlanOutdated { playlist: String },
+    Other(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Auth(reason) => write!(f, "Authentication failed: {}", reason),
+            Error::RateLimited { url, retry_after: Some(seconds) } => write!(f, "Rate limited on {}, retry after {}s", url, seconds),
+            Error::RateLimited { url, retry_after: None } => write!(f, "Rate limited on {}", url),
+            Error::NotFound { url } => write!(f, "Not found: {}", url),
+            Error::Http { status, url, body } if body.is_empty() => write!(f, "{} failed with {}", url, status),
+            Error::Http { status, url, body } => write!(f, "{} failed with {}: {}", url, status, body),
+            Error::Decode(reason) => write!(f, "Unexpected response: {}", reason),
+            Error::Config(reason) => write!(f, "Configuration error: {}", reason),
+            Error::Request(e) => write!(f, "Request failed: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::PlanOutdated { playlist } => write!(f, "Spotify playlist of '{}' changed since the plan was made", playlist),
+            Error::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Request(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Turns unsuccessful responses into the matching error, so callers only see successful ones.
+pub async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let url = response.url().to_string();
+    match status {
+        // 403 is left to the generic case: Spotify uses it for refusals of a single resource, such as
+        // changing a playlist owned by someone else, which say nothing about the login itself
+        StatusCode::UNAUTHORIZED => {
+            let body = response.text().await.unwrap_or_default();
+            Err(Error::Auth(format!("{} was refused with {}: {}", url, status, body)))
+        }
+        StatusCode::NOT_FOUND => Err(Error::NotFound { url }),
+        StatusCode::TOO_MANY_REQUESTS => {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok());
+            Err(Error::RateLimited { url, retry_after })
+        }
+        _ => {
+            let body = response.text().await.unwrap_or_default();
+            Err(Error::Http { status, url, body })
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Error::Decode(e.to_string())
+        } else {
+            Error::Request(e)
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<oauth2::url::ParseError> for Error {
+    fn from(e: oauth2::url::ParseError) -> Self {
+        Error::Config(format!("Invalid URL: {}", e))
+    }
+}
+
+impl From<ConfigurationError> for Error {
+    fn from(e: ConfigurationError) -> Self {
+        Error::Config(e.to_string())
+    }
+}
+
+/// Token endpoint errors, with the provider's error description where it sent one. Only errors the
+/// provider answered with are authentication failures, not being able to reach it is not.
+impl<RE, T> From<RequestTokenError<RE, T>> for Error
+where
+    RE: std::error::Error + Into<Error>,
+    T: ErrorResponse + fmt::Display,
+{
+    fn from(e: RequestTokenError<RE, T>) -> Self {
+        match e {
+            RequestTokenError::ServerResponse(response) => Error::Auth(response.to_string()),
+            RequestTokenError::Request(e) => e.into(),
+            RequestTokenError::Parse(e, _) => Error::Decode(format!("Invalid token response: {}", e)),
+            RequestTokenError::Other(reason) => Error::Auth(reason),
+        }
+    }
+}
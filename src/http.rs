@@ -0,0 +1,132 @@
+use crate::config::HttpConfig;
+use crate::error::Result;
+use oauth2::{HttpRequest, HttpResponse};
+use rand::Rng;
+use reqwest::{redirect, Client, IntoUrl, Method, RequestBuilder, Response, StatusCode};
+use tokio::time::{sleep, Duration};
+
+/// First retry delay, doubled for every further retry
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+/// Longest `Retry-After` we are willing to wait for, longer ones are treated as failures
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// The request layer every API call goes through: one shared connection pool, and retries with
+/// exponential backoff for transient failures.
+///
+/// Network errors and 5xx responses are only retried for idempotent methods, since a `POST` that
+/// failed halfway might have been applied already. Rate limited (429) requests weren't processed
+/// at all, so they are retried for any method after the `Retry-After` delay the service asked for.
+/// Requests to OAuth token endpoints are the exception, see [`Self::send_token_request`].
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    /// Client for OAuth token endpoints, which doesn't follow redirects: that would send the client
+    /// secret and the code or refresh token on to wherever the redirect points
+    token_client: Client,
+    max_retries: u32,
+}
+
+impl HttpClient {
+    pub fn new(config: &HttpConfig) -> Result<Self> {
+        Ok(Self {
+            client: Client::new(),
+            token_client: Client::builder().redirect(redirect::Policy::none()).build()?,
+            max_retries: config.max_retries,
+        })
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// A `POST` to an OAuth endpoint, which won't follow redirects.
+    pub fn token_post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.token_client.post(url)
+    }
+
+    pub fn put<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.put(url)
+    }
+
+    pub fn delete<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.delete(url)
+    }
+
+    /// Sends the request, retrying it up to the configured number of times. The last response is
+    /// returned as is, whatever its status.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let idempotent = request
+            .try_clone()
+            .and_then(|request| request.build().ok())
+            .is_some_and(|request| is_idempotent(request.method()));
+        self.send_with_retries(request, idempotent).await
+    }
+
+    /// Sends a request of the `oauth2` crate to a token or revocation endpoint.
+    ///
+    /// These are `POST`s, but safe to send again after a failure: an authorization code or refresh
+    /// token the provider already used is just rejected, and revoking a token twice does nothing.
+    pub async fn send_token_request(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let request = self.token_client.request(request.method, request.url).headers(request.headers).body(request.body);
+        let response = self.send_with_retries(request, true).await?;
+        Ok(HttpResponse {
+            status_code: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes().await?.to_vec(),
+        })
+    }
+
+    /// Network errors and 5xx responses are only retried if `idempotent` is set.
+    async fn send_with_retries(&self, request: RequestBuilder, idempotent: bool) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            // Requests with streaming bodies can't be cloned and so can't be retried
+            let Some(retry) = request.try_clone().filter(|_| attempt < self.max_retries) else {
+                return Ok(request.send().await?);
+            };
+
+            let delay = match retry.send().await {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => match retry_after(&response) {
+                    Some(delay) if delay > MAX_RETRY_AFTER => return Ok(response),
+                    Some(delay) => delay,
+                    None => backoff(attempt),
+                },
+                Ok(response) if response.status().is_server_error() && idempotent => {
+                    log::debug!("{} answered {}", response.url(), response.status());
+                    backoff(attempt)
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if idempotent && (e.is_connect() || e.is_timeout() || e.is_request()) => {
+                    log::debug!("Request failed: {}", e);
+                    backoff(attempt)
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            attempt += 1;
+            log::warn!("Retrying request in {:.1}s (retry {} of {})", delay.as_secs_f64(), attempt, self.max_retries);
+            sleep(delay).await;
+        }
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS)
+}
+
+/// Exponential backoff with jitter, so clients that failed together don't all retry at the same time.
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_DELAY);
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+/// The delay the service asked for in its `Retry-After` header, in seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds: u64 = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
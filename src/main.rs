@@ -3,21 +3,124 @@ mod spotify;
 mod sync;
 mod config;
 mod utils;
-use env_logger;
+mod db;
+mod diff;
+mod matcher;
+mod report;
+mod plan;
+mod cli;
+mod commands;
+mod callback;
+mod oauth;
+mod token_store;
+mod vault;
+mod session;
+mod device;
+mod error;
+mod http;
 
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use cli::{AuthArgs, AuthCommand, Cli, Command, Service};
+use error::Result;
+use report::PlaylistFailure;
+use std::process::ExitCode;
+
+
+/// Exit status when some playlists failed to sync but the run itself completed
+const EXIT_PLAYLISTS_FAILED: u8 = 3;
 
 #[tokio::main]
-async fn main() {
-    // Initialize logging
-    env_logger::init();
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-    // Load configuration
-    let config = config::load_config().expect("Failed to load configuration");
+    // Initialize logging, RUST_LOG still takes precedence over the verbosity flags
+    env_logger::Builder::new()
+        .filter_level(cli.log_level())
+        .parse_default_env()
+        .init();
 
-    // Authenticate with Tidal and Spotify
-    let tidal_client = tidal::auth::authenticate(&config).await.unwrap();
-    let spotify_client = spotify::auth::authenticate(&config).await.unwrap();
+    match run(cli).await {
+        Ok(failures) if failures.is_empty() => ExitCode::SUCCESS,
+        Ok(failures) => {
+            report::print_failures(&failures);
+            ExitCode::from(EXIT_PLAYLISTS_FAILED)
+        }
+        Err(e) => {
+            utils::log_error(&e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the command, returning the playlists that failed to sync.
+async fn run(cli: Cli) -> Result<Vec<PlaylistFailure>> {
+    // Load configuration
+    let config = config::load_config(&cli.config)?;
 
-    // Perform sync
-    sync::sync_data(&tidal_client, &spotify_client).await.unwrap();
+    match cli.command.unwrap_or(Command::Sync { dry_run: false, force: false }) {
+        Command::Sync { dry_run, force } => {
+            let tidal_client = tidal::auth::authenticate(&config).await?;
+            let spotify_client = spotify::auth::authenticate(&config).await?;
+            return sync::sync_data(&config, &tidal_client, &spotify_client, &cli.playlists, dry_run, force).await;
+        }
+        Command::Diff { force } => {
+            let tidal_client = tidal::auth::authenticate(&config).await?;
+            let spotify_client = spotify::auth::authenticate(&config).await?;
+            return sync::sync_data(&config, &tidal_client, &spotify_client, &cli.playlists, true, force).await;
+        }
+        Command::Plan { path, force } => {
+            let tidal_client = tidal::auth::authenticate(&config).await?;
+            let spotify_client = spotify::auth::authenticate(&config).await?;
+            return sync::write_sync_plan(&config, &tidal_client, &spotify_client, &cli.playlists, &path, force).await;
+        }
+        Command::Apply { path } => {
+            let spotify_client = spotify::auth::authenticate(&config).await?;
+            return sync::apply_sync_plan(&spotify_client, &path).await;
+        }
+        Command::Auth(AuthArgs { command: Some(AuthCommand::Start { service }), .. }) => {
+            commands::start_login(&config, service)?;
+        }
+        Command::Auth(AuthArgs { command: Some(AuthCommand::Finish { redirect_url }), .. }) => {
+            commands::finish_login(&config, &redirect_url).await?;
+        }
+        Command::Auth(AuthArgs { command: Some(AuthCommand::Status), .. }) => {
+            commands::print_auth_status(&config).await?;
+        }
+        Command::Auth(AuthArgs { command: Some(AuthCommand::Refresh { service }), .. }) => {
+            commands::refresh_login(&config, service).await?;
+        }
+        Command::Auth(AuthArgs { command: Some(AuthCommand::Logout { service }), .. }) => {
+            commands::logout(&config, service).await?;
+        }
+        Command::Auth(AuthArgs { service, device, command: None }) => {
+            if device && service == Some(Service::Spotify) {
+                Cli::command()
+                    .error(ErrorKind::ArgumentConflict, "Spotify doesn't support logging in with a device code")
+                    .exit();
+            }
+            if service != Some(Service::Spotify) {
+                if device {
+                    tidal::auth::authenticate_device(&config).await?;
+                } else {
+                    tidal::auth::authenticate(&config).await?;
+                }
+                println!("Logged in to Tidal");
+            }
+            if service != Some(Service::Tidal) {
+                spotify::auth::authenticate(&config).await?;
+                println!("Logged in to Spotify");
+            }
+        }
+        Command::List => {
+            let tidal_client = tidal::auth::authenticate(&config).await?;
+            commands::list_playlists(&tidal_client, &cli.playlists).await?;
+        }
+        Command::Export { output } => {
+            let tidal_client = tidal::auth::authenticate(&config).await?;
+            commands::export_playlists(&tidal_client, &cli.playlists, output.as_deref()).await?;
+        }
+        Command::Status => commands::print_status()?,
+    }
+    Ok(Vec::new())
 }
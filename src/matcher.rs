@@ -0,0 +1,343 @@
+use crate::spotify::data::SpotifyTrack;
+use crate::tidal::data::TidalTrack;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Candidates scoring below this are not considered a match at all
+pub const MIN_CONFIDENCE: f64 = 0.6;
+/// Matches below this confidence are accepted but flagged for review
+pub const LOW_CONFIDENCE: f64 = 0.85;
+
+/// Durations within this many seconds of each other count as identical
+const DURATION_TOLERANCE_SECS: f64 = 3.0;
+/// Durations further apart than this get no duration score
+const DURATION_CUTOFF_SECS: f64 = 20.0;
+
+#[derive(Debug, Clone)]
+pub struct FuzzyMatch {
+    pub uri: String,
+    pub confidence: f64,
+}
+
+/// Why an ISRC search result ranked where it did. Criteria are compared in field order.
+#[derive(Debug, Clone)]
+pub struct IsrcRanking {
+    pub uri: String,
+    pub playable: bool,
+    pub album_type: String,
+    pub explicit_matches: bool,
+    /// Absolute duration difference in seconds, `None` if Tidal didn't report a duration
+    pub duration_difference: Option<u64>,
+}
+
+impl IsrcRanking {
+    fn album_type_rank(&self) -> u8 {
+        match self.album_type.as_str() {
+            "album" => 2,
+            "single" => 1,
+            _ => 0,
+        }
+    }
+
+    /// `Ordering::Less` means `self` is the better candidate.
+    fn compare(&self, other: &Self) -> Ordering {
+        other
+            .playable
+            .cmp(&self.playable)
+            .then(other.album_type_rank().cmp(&self.album_type_rank()))
+            .then(other.explicit_matches.cmp(&self.explicit_matches))
+            .then(self.duration_difference.unwrap_or(u64::MAX).cmp(&other.duration_difference.unwrap_or(u64::MAX)))
+    }
+}
+
+impl fmt::Display for IsrcRanking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (playable: {}, album type: {}, explicit matches: {}, duration difference: {})",
+            self.uri,
+            self.playable,
+            self.album_type,
+            self.explicit_matches,
+            self.duration_difference.map(|secs| format!("{}s", secs)).unwrap_or_else(|| "unknown".to_string()),
+        )
+    }
+}
+
+/// Ranks the results of an ISRC search, best candidate first: playable in the user's market, then
+/// album releases over singles and compilations, then matching explicit flag, then closest duration.
+pub fn rank_isrc_candidates(track: &TidalTrack, candidates: &[SpotifyTrack]) -> Vec<IsrcRanking> {
+    let duration = track.attributes.duration_secs();
+    let mut rankings: Vec<IsrcRanking> = candidates
+        .iter()
+        .map(|candidate| IsrcRanking {
+            uri: candidate.uri.clone(),
+            // Without market information, assume the track is available
+            playable: candidate.is_playable.unwrap_or(true),
+            album_type: candidate.album.album_type.clone(),
+            explicit_matches: candidate.explicit == track.attributes.explicit,
+            duration_difference: duration.map(|duration| duration.abs_diff((candidate.duration_ms as f64 / 1000.0).round() as u64)),
+        })
+        .collect();
+    rankings.sort_by(IsrcRanking::compare);
+    rankings
+}
+
+/// Builds the Spotify search queries to try for a track, most specific first.
+pub fn search_queries(track: &TidalTrack) -> Vec<String> {
+    let title = strip_decorations(&track.attributes.title);
+    let mut base = format!("track:{}", title);
+    if let Some(artist) = track.artists.first() {
+        base.push_str(&format!(" artist:{}", artist));
+    }
+
+    let mut queries = Vec::new();
+    if let Some(album) = &track.album {
+        queries.push(format!("{} album:{}", base, strip_decorations(album)));
+    }
+    queries.push(base);
+    queries
+}
+
+/// Picks the best scoring candidate, if any scores at least [`MIN_CONFIDENCE`].
+pub fn best_match(track: &TidalTrack, candidates: &[SpotifyTrack]) -> Option<FuzzyMatch> {
+    candidates
+        .iter()
+        .map(|candidate| FuzzyMatch { uri: candidate.uri.clone(), confidence: score(track, candidate) })
+        .filter(|candidate| candidate.confidence >= MIN_CONFIDENCE)
+        .max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+}
+
+/// Weighted score in `0.0..=1.0` from title similarity, primary artist and duration closeness.
+pub fn score(track: &TidalTrack, candidate: &SpotifyTrack) -> f64 {
+    let title = similarity(&normalize(&track.attributes.title), &normalize(&candidate.name));
+
+    let artist = match track.artists.first() {
+        Some(artist) => {
+            let artist = normalize(artist);
+            candidate.artists.iter().map(|other| similarity(&artist, &normalize(&other.name))).fold(0.0, f64::max)
+        }
+        // Without artist information, don't let it count against the candidate
+        None => 1.0,
+    };
+
+    let duration = match track.attributes.duration_secs() {
+        Some(duration) => duration_score(duration as f64, candidate.duration_ms as f64 / 1000.0),
+        None => 0.5,
+    };
+
+    0.55 * title + 0.25 * artist + 0.2 * duration
+}
+
+fn duration_score(a: f64, b: f64) -> f64 {
+    let difference = (a - b).abs();
+    if difference <= DURATION_TOLERANCE_SECS {
+        1.0
+    } else {
+        (1.0 - (difference - DURATION_TOLERANCE_SECS) / (DURATION_CUTOFF_SECS - DURATION_TOLERANCE_SECS)).max(0.0)
+    }
+}
+
+/// Drops bracketed suffixes such as "(Remastered 2011)" or "[feat. X]" that differ between services.
+fn strip_decorations(title: &str) -> String {
+    let mut result = String::new();
+    let mut depth = 0;
+    for c in title.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            _ if depth == 0 => result.push(c),
+            _ => {}
+        }
+    }
+    let result = match result.split_once(" - ") {
+        Some((head, _)) => head,
+        None => &result,
+    };
+    result.trim().to_string()
+}
+
+fn normalize(title: &str) -> String {
+    strip_decorations(title)
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Normalized Levenshtein similarity in `0.0..=1.0`.
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::data::{SpotifyAlbum, SpotifyArtist};
+    use serde_json::json;
+
+    fn tidal_track(title: &str, artist: &str, duration: &str) -> TidalTrack {
+        let links = json!({ "links": { "self": "" } });
+        let mut track: TidalTrack = serde_json::from_value(json!({
+            "id": "1",
+            "attributes": {
+                "title": title,
+                "isrc": "USRC17607839",
+                "duration": duration,
+                "explicit": false,
+                "popularity": 0.5,
+                "availability": [],
+                "mediaTags": [],
+                "externalLinks": [],
+                "copyright": "",
+            },
+            "relationships": { "albums": links, "artists": links, "providers": links, "radio": links, "similarTracks": links },
+            "links": { "self": "" },
+        }))
+        .unwrap();
+        track.artists = vec![artist.to_string()];
+        track
+    }
+
+    fn spotify_track(uri: &str, name: &str, artist: &str, duration_ms: u64) -> SpotifyTrack {
+        SpotifyTrack {
+            uri: uri.to_string(),
+            name: name.to_string(),
+            duration_ms,
+            explicit: false,
+            is_playable: None,
+            artists: vec![SpotifyArtist { name: artist.to_string() }],
+            album: SpotifyAlbum { name: "Album".to_string(), album_type: "album".to_string() },
+        }
+    }
+
+    #[test]
+    fn parses_tidal_durations() {
+        assert_eq!(tidal_track("", "", "PT3M25S").attributes.duration_secs(), Some(205));
+        assert_eq!(tidal_track("", "", "PT1H2M3.5S").attributes.duration_secs(), Some(3724));
+        assert_eq!(tidal_track("", "", "PT45S").attributes.duration_secs(), Some(45));
+        assert_eq!(tidal_track("", "", "3:25").attributes.duration_secs(), None);
+        assert_eq!(tidal_track("", "", "PTxM").attributes.duration_secs(), None);
+    }
+
+    #[test]
+    fn strips_decorations() {
+        assert_eq!(strip_decorations("Yesterday (Remastered 2009)"), "Yesterday");
+        assert_eq!(strip_decorations("Song [feat. Someone] - Radio Edit"), "Song");
+        assert_eq!(strip_decorations("Unbalanced) Title ("), "Unbalanced Title");
+        assert_eq!(normalize("Don't Stop Me Now (Live)"), "don t stop me now");
+    }
+
+    #[test]
+    fn similarity_is_normalized_edit_distance() {
+        assert_eq!(similarity("abc", "abc"), 1.0);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("abc", ""), 0.0);
+        assert!((similarity("kitten", "sitting") - (1.0 - 3.0 / 7.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn decorated_titles_match_with_full_confidence() {
+        let track = tidal_track("Yesterday (Remastered 2009)", "The Beatles", "PT2M5S");
+        let candidate = spotify_track("spotify:track:a", "Yesterday - Remastered 2009", "The Beatles", 125_000);
+        assert_eq!(score(&track, &candidate), 1.0);
+    }
+
+    #[test]
+    fn durations_only_count_within_the_cutoff() {
+        let track = tidal_track("Song", "Artist", "PT3M0S");
+        let close = spotify_track("spotify:track:a", "Song", "Artist", 182_000);
+        // Halfway between the tolerance and the cutoff
+        let halfway = spotify_track("spotify:track:b", "Song", "Artist", 191_500);
+        let far = spotify_track("spotify:track:c", "Song", "Artist", 210_000);
+
+        assert_eq!(score(&track, &close), 1.0);
+        assert!((score(&track, &halfway) - 0.9).abs() < 1e-9);
+        // Title and artist alone are still a match, but one to review
+        let far_score = score(&track, &far);
+        assert!((far_score - 0.8).abs() < 1e-9);
+        assert!((MIN_CONFIDENCE..LOW_CONFIDENCE).contains(&far_score));
+    }
+
+    #[test]
+    fn best_match_requires_minimum_confidence() {
+        let track = tidal_track("Song", "Artist", "PT3M0S");
+        let other = spotify_track("spotify:track:a", "Completely Different", "Someone Else", 180_000);
+        assert!(best_match(&track, std::slice::from_ref(&other)).is_none());
+
+        let wrong_artist = spotify_track("spotify:track:b", "Song", "Someone Else", 180_000);
+        let right = spotify_track("spotify:track:c", "Song", "Artist", 181_000);
+        let found = best_match(&track, &[other, wrong_artist, right]).unwrap();
+        assert_eq!(found.uri, "spotify:track:c");
+        assert!(found.confidence >= LOW_CONFIDENCE);
+    }
+
+    fn release(uri: &str, album_type: &str, playable: Option<bool>, explicit: bool, duration_ms: u64) -> SpotifyTrack {
+        let mut track = spotify_track(uri, "Song", "Artist", duration_ms);
+        track.album.album_type = album_type.to_string();
+        track.is_playable = playable;
+        track.explicit = explicit;
+        track
+    }
+
+    fn ranked_uris(track: &TidalTrack, candidates: &[SpotifyTrack]) -> Vec<String> {
+        rank_isrc_candidates(track, candidates).into_iter().map(|ranking| ranking.uri).collect()
+    }
+
+    #[test]
+    fn ranks_albums_over_singles_over_compilations() {
+        let track = tidal_track("Song", "Artist", "PT3M0S");
+        let candidates = [
+            release("compilation", "compilation", Some(true), false, 180_000),
+            release("single", "single", Some(true), false, 180_000),
+            release("album", "album", Some(true), false, 180_000),
+        ];
+        assert_eq!(ranked_uris(&track, &candidates), ["album", "single", "compilation"]);
+    }
+
+    #[test]
+    fn ranks_playable_releases_first() {
+        let track = tidal_track("Song", "Artist", "PT3M0S");
+        let candidates = [
+            release("unplayable album", "album", Some(false), false, 180_000),
+            release("playable compilation", "compilation", Some(true), false, 180_000),
+            release("unknown album", "album", None, false, 180_000),
+        ];
+        assert_eq!(ranked_uris(&track, &candidates), ["unknown album", "playable compilation", "unplayable album"]);
+    }
+
+    #[test]
+    fn ranks_matching_explicit_flag_then_closest_duration() {
+        let track = tidal_track("Song", "Artist", "PT3M0S");
+        let candidates = [
+            release("explicit", "album", Some(true), true, 180_000),
+            release("far", "album", Some(true), false, 190_000),
+            release("close", "album", Some(true), false, 181_000),
+        ];
+        assert_eq!(ranked_uris(&track, &candidates), ["close", "far", "explicit"]);
+
+        let rankings = rank_isrc_candidates(&track, &candidates);
+        assert_eq!(rankings[0].duration_difference, Some(1));
+        assert!(rank_isrc_candidates(&track, &[]).is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::callback::{parse_callback, receive_authorization_code, CallbackError};
+use crate::config::OAuthConfig;
+use crate::db::now;
+use crate::device::authorize_device;
+use crate::error::{Error, Result};
+use crate::http::HttpClient;
+use crate::token_store::{PendingLogin, StoredToken, TokenStore};
+use oauth2::basic::{BasicClient, BasicTokenResponse};
+use oauth2::url::Url;
+use oauth2::{
+    AccessToken, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, PkceCodeVerifier,
+    RedirectUrl, RefreshToken, RevocationUrl, Scope, StandardRevocableToken, TokenResponse, TokenUrl,
+};
+
+/// How long, in seconds, a login started with `auth start` can be finished. Authorization codes are
+/// short-lived too, so older redirects would be refused by the provider anyway.
+const PENDING_LOGIN_TTL: u64 = 10 * 60;
+
+/// The OAuth endpoints, scopes and token storage of one streaming service.
+pub struct OAuthProvider {
+    pub name: &'static str,
+    pub auth_url: &'static str,
+    pub token_url: &'static str,
+    pub scopes: &'static [&'static str],
+    /// Endpoint for revoking tokens on logout, for providers that support it
+    pub revocation_url: Option<&'static str>,
+    /// Endpoint of the device authorization flow, for providers that support it
+    pub device_authorization_url: Option<&'static str>,
+    /// Name of this provider's tokens in the [`TokenStore`]
+    pub token_key: &'static str,
+    /// Token file in the working directory used by older versions, migrated on first use
+    pub legacy_token_file: &'static str,
+}
+
+impl OAuthProvider {
+    fn client(&self, config: &OAuthConfig) -> Result<BasicClient> {
+        Ok(BasicClient::new(
+            ClientId::new(config.client_id.clone()),
+            Some(ClientSecret::new(config.client_secret.clone())),
+            AuthUrl::new(self.auth_url.to_string())?,
+            Some(TokenUrl::new(self.token_url.to_string())?),
+        )
+        .set_redirect_uri(RedirectUrl::new(config.redirect_uri.clone())?))
+    }
+
+    /// Returns valid tokens, from storage if possible, refreshing them if they expired, or by
+    /// running the browser authorization flow.
+    pub async fn authenticate(&self, config: &OAuthConfig, store: &TokenStore, http: &HttpClient) -> Result<StoredToken> {
+        if let Some(token) = store.load(self.token_key, self.legacy_token_file)? {
+            match (&token.refresh_token, token.is_expired()) {
+                (_, false) => return Ok(token),
+                (Some(refresh_token), true) => return self.refresh_access_token(refresh_token, config, store, http).await,
+                (None, true) => log::info!("{} token expired and can't be refreshed, logging in again", self.name),
+            }
+        }
+
+        let client = self.client(config)?;
+        let (auth_url, csrf_token, pkce_verifier) = self.authorize_url(&client);
+        println!("Open this URL in your browser to log in to {}:\n{}", self.name, auth_url);
+        let auth_code = receive_authorization_code(&config.redirect_uri, &csrf_token).await?;
+        self.exchange_code(&client, auth_code, pkce_verifier, store, http).await
+    }
+
+    /// Logs in by having the user enter a code on another device, replacing any stored tokens.
+    pub async fn authenticate_device(&self, config: &OAuthConfig, store: &TokenStore, http: &HttpClient) -> Result<StoredToken> {
+        let device_authorization_url = self
+            .device_authorization_url
+            .ok_or_else(|| Error::Auth(format!("{} doesn't support logging in with a device code", self.name)))?;
+        let token_result = authorize_device(self, device_authorization_url, config, http).await?;
+
+        let token = self.stored_token(&token_result, None);
+        store.save(self.token_key, &token)?;
+        Ok(token)
+    }
+
+    /// First half of a login on a machine without a browser: saves the PKCE verifier and state to
+    /// the store and returns the URL to open elsewhere.
+    pub fn start_login(&self, config: &OAuthConfig, store: &TokenStore) -> Result<Url> {
+        let (auth_url, csrf_token, pkce_verifier) = self.authorize_url(&self.client(config)?);
+        let pending = PendingLogin {
+            csrf_state: csrf_token.secret().to_string(),
+            pkce_verifier: pkce_verifier.secret().to_string(),
+            started_at: now(),
+        };
+        store.save_pending(self.token_key, &pending)?;
+        Ok(auth_url)
+    }
+
+    /// Completes a login started with [`Self::start_login`] using the URL the browser was redirected to.
+    ///
+    /// Returns `None` if there is no pending login, it expired, or the redirect belongs to a different one.
+    pub async fn finish_login(
+        &self,
+        config: &OAuthConfig,
+        store: &TokenStore,
+        http: &HttpClient,
+        redirect_url: &str,
+    ) -> Result<Option<StoredToken>> {
+        let Some(pending) = store.load_pending(self.token_key)? else {
+            return Ok(None);
+        };
+        if now().saturating_sub(pending.started_at) > PENDING_LOGIN_TTL {
+            log::warn!("The {} login was started more than {} minutes ago and has expired", self.name, PENDING_LOGIN_TTL / 60);
+            store.remove_pending(self.token_key)?;
+            return Ok(None);
+        }
+        let auth_code = match parse_callback(redirect_url, &CsrfToken::new(pending.csrf_state)) {
+            Ok(auth_code) => auth_code,
+            Err(CallbackError::StateMismatch) => return Ok(None),
+            // The redirect answered this login, so it can't be completed any more
+            Err(e @ (CallbackError::Provider { .. } | CallbackError::MissingCode)) => {
+                store.remove_pending(self.token_key)?;
+                return Err(e.into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let token = self
+            .exchange_code(&self.client(config)?, auth_code, PkceCodeVerifier::new(pending.pkce_verifier), store, http)
+            .await?;
+        store.remove_pending(self.token_key)?;
+        Ok(Some(token))
+    }
+
+    fn authorize_url(&self, client: &BasicClient) -> (Url, CsrfToken, PkceCodeVerifier) {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (auth_url, csrf_token) = client
+            .authorize_url(CsrfToken::new_random)
+            .add_scopes(self.scopes.iter().map(|scope| Scope::new(scope.to_string())))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        (auth_url, csrf_token, pkce_verifier)
+    }
+
+    async fn exchange_code(
+        &self,
+        client: &BasicClient,
+        auth_code: AuthorizationCode,
+        pkce_verifier: PkceCodeVerifier,
+        store: &TokenStore,
+        http: &HttpClient,
+    ) -> Result<StoredToken> {
+        let token_result = client
+            .exchange_code(auth_code)
+            .set_pkce_verifier(pkce_verifier)
+            .request_async(|request| http.send_token_request(request))
+            .await?;
+
+        let token = self.stored_token(&token_result, None);
+        store.save(self.token_key, &token)?;
+        Ok(token)
+    }
+
+    pub async fn refresh_access_token(&self, refresh_token: &str, config: &OAuthConfig, store: &TokenStore, http: &HttpClient) -> Result<StoredToken> {
+        let token_result = self
+            .client(config)?
+            .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
+            .request_async(|request| http.send_token_request(request))
+            .await?;
+
+        let token = self.stored_token(&token_result, Some(refresh_token));
+        store.save(self.token_key, &token)?;
+        Ok(token)
+    }
+
+    /// Revokes the tokens at the provider. Returns `false` if the provider doesn't support revocation.
+    ///
+    /// Revoking the refresh token also invalidates the access tokens issued with it, so that one is preferred.
+    pub async fn revoke(&self, config: &OAuthConfig, token: &StoredToken, http: &HttpClient) -> Result<bool> {
+        let Some(revocation_url) = self.revocation_url else {
+            return Ok(false);
+        };
+        let revocable_token = match &token.refresh_token {
+            Some(refresh_token) => StandardRevocableToken::RefreshToken(RefreshToken::new(refresh_token.clone())),
+            None => StandardRevocableToken::AccessToken(AccessToken::new(token.access_token.clone())),
+        };
+
+        self.client(config)?
+            .set_revocation_uri(RevocationUrl::new(revocation_url.to_string())?)
+            .revoke_token(revocable_token)?
+            .request_async(|request| http.send_token_request(request))
+            .await?;
+        Ok(true)
+    }
+
+    /// Providers may or may not rotate the refresh token or echo the granted scopes, so fall back to
+    /// the previous refresh token and the scopes we asked for.
+    fn stored_token(&self, token_result: &BasicTokenResponse, previous_refresh_token: Option<&str>) -> StoredToken {
+        StoredToken {
+            access_token: token_result.access_token().secret().to_string(),
+            refresh_token: token_result
+                .refresh_token()
+                .map(|token| token.secret().to_string())
+                .or_else(|| previous_refresh_token.map(str::to_string)),
+            token_type: token_result.token_type().as_ref().to_string(),
+            scopes: match token_result.scopes() {
+                Some(scopes) => scopes.iter().map(|scope| scope.to_string()).collect(),
+                None => self.scopes.iter().map(|scope| scope.to_string()).collect(),
+            },
+            expires_at: token_result.expires_in().map(|expires_in| now() + expires_in.as_secs()),
+            obtained_at: now(),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::db::now;
+use crate::diff::PlaylistChange;
+use crate::error::Result;
+use crate::report::UnmatchedTrack;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A reviewed set of Spotify changes, written by `plan` and executed as-is by `apply`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SyncPlan {
+    pub created_at: u64,
+    pub playlists: Vec<PlaylistPlan>,
+}
+
+/// Everything needed to bring one Spotify playlist in line with its Tidal counterpart.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlaylistPlan {
+    pub tidal_playlist_id: String,
+    pub name: String,
+    /// `None` if the playlist hasn't been synced before and will be created
+    pub spotify_playlist_id: Option<String>,
+    pub snapshot_id: Option<String>,
+    pub changes: Vec<PlaylistChange>,
+    pub unmatched: Vec<UnmatchedTrack>,
+    /// Human readable names of the tracks referenced by `changes`, keyed by URI
+    pub track_names: HashMap<String, String>,
+    pub last_modified_at: Option<String>,
+    pub number_of_items: Option<u32>,
+}
+
+impl SyncPlan {
+    pub fn new(playlists: Vec<PlaylistPlan>) -> Self {
+        Self { created_at: now(), playlists }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        for playlist in &self.playlists {
+            print_playlist_plan(playlist);
+        }
+    }
+}
+
+pub fn print_playlist_plan(plan: &PlaylistPlan) {
+    let track_name = |uri: &String| plan.track_names.get(uri).cloned().unwrap_or_else(|| uri.clone());
+
+    match &plan.spotify_playlist_id {
+        Some(spotify_playlist_id) => println!("Playlist '{}' (Spotify playlist {})", plan.name, spotify_playlist_id),
+        None => println!("Playlist '{}' (will be created on Spotify)", plan.name),
+    }
+    if plan.changes.is_empty() && plan.unmatched.is_empty() {
+        println!("  no changes");
+    }
+    for change in &plan.changes {
+        match change {
+            PlaylistChange::Remove { uris } => {
+                for uri in uris {
+                    println!("  - {} ({})", track_name(uri), uri);
+                }
+            }
+            PlaylistChange::Add { uris, position } => {
+                for (offset, uri) in uris.iter().enumerate() {
+                    println!("  + {} ({}) at position {}", track_name(uri), uri, position + offset + 1);
+                }
+            }
+            PlaylistChange::Move { range_start, insert_before } => {
+                println!("  ~ move track at position {} to before position {}", range_start + 1, insert_before + 1);
+            }
+        }
+    }
+    for track in &plan.unmatched {
+        println!("  ? {} [ISRC {}]: no match on Spotify", track.title, track.isrc);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::db::{now, SyncDatabase};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs;
+
+const JSON_REPORT_PATH: &str = "unmatched_tracks.json";
+const TEXT_REPORT_PATH: &str = "unmatched_tracks.txt";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnmatchedTrack {
+    pub tidal_id: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub isrc: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaylistReport {
+    pub tidal_playlist_id: String,
+    pub name: String,
+    pub unmatched: Vec<UnmatchedTrack>,
+}
+
+/// A playlist that couldn't be synced. The other playlists are synced regardless.
+#[derive(Debug)]
+pub struct PlaylistFailure {
+    pub name: String,
+    pub error: Error,
+}
+
+/// Prints which playlists failed and why, after the rest of the run's output.
+pub fn print_failures(failures: &[PlaylistFailure]) {
+    eprintln!("{} playlists failed to sync:", failures.len());
+    for failure in failures {
+        eprintln!("  {}: {}", failure.name, failure.error);
+    }
+}
+
+/// Tracks that couldn't be matched on Spotify, grouped by playlist.
+#[derive(Serialize, Debug)]
+pub struct SyncReport {
+    pub generated_at: u64,
+    pub playlists: Vec<PlaylistReport>,
+}
+
+impl SyncReport {
+    /// Reports the unmatched tracks of every playlist as of its last sync, so playlists that were
+    /// skipped as unchanged or failed in this run are still listed.
+    pub fn from_database(db: &SyncDatabase) -> Self {
+        let mut playlists: Vec<PlaylistReport> = db.unmatched_tracks().cloned().collect();
+        playlists.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.tidal_playlist_id.cmp(&b.tidal_playlist_id)));
+        Self { generated_at: now(), playlists }
+    }
+
+    pub fn unmatched_count(&self) -> usize {
+        self.playlists.iter().map(|playlist| playlist.unmatched.len()).sum()
+    }
+
+    /// Writes the report next to the sync database, as JSON for tooling and plain text for people.
+    pub fn write(&self) -> Result<()> {
+        fs::write(JSON_REPORT_PATH, serde_json::to_string_pretty(self)?)?;
+        fs::write(TEXT_REPORT_PATH, self.to_text())?;
+        Ok(())
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        if self.playlists.is_empty() {
+            text.push_str("All tracks were matched.\n");
+            return text;
+        }
+
+        for playlist in &self.playlists {
+            let _ = writeln!(text, "{} ({} unmatched)", playlist.name, playlist.unmatched.len());
+            for track in &playlist.unmatched {
+                let _ = writeln!(
+                    text,
+                    "  - {} by {} [ISRC {}, Tidal id {}]: {}",
+                    track.title,
+                    if track.artists.is_empty() { "unknown artist".to_string() } else { track.artists.join(", ") },
+                    track.isrc,
+                    track.tidal_id,
+                    track.reason,
+                );
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
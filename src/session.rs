@@ -0,0 +1,59 @@
+use crate::config::OAuthConfig;
+use crate::error::{check_status, Error, Result};
+use crate::http::HttpClient;
+use crate::oauth::OAuthProvider;
+use crate::token_store::{StoredToken, TokenStore};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use tokio::sync::Mutex;
+
+/// The logged in state of one service: its current tokens and everything needed to refresh them.
+pub struct AuthSession {
+    provider: &'static OAuthProvider,
+    config: OAuthConfig,
+    store: TokenStore,
+    token: Mutex<StoredToken>,
+    http: HttpClient,
+}
+
+impl AuthSession {
+    pub fn new(provider: &'static OAuthProvider, config: OAuthConfig, store: TokenStore, token: StoredToken, http: HttpClient) -> Self {
+        Self { provider, config, store, token: Mutex::new(token), http }
+    }
+
+    pub fn http(&self) -> &HttpClient {
+        &self.http
+    }
+
+    /// Sends the request with the current access token, turning unsuccessful responses into errors.
+    ///
+    /// Access tokens can expire in the middle of a long sync, so when the service answers 401 the
+    /// token is refreshed, saved, and the request is sent once more with the new one.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let retry = request.try_clone();
+        let access_token = self.token.lock().await.access_token.clone();
+        let response = self.http.send(request.bearer_auth(&access_token)).await?;
+
+        match retry {
+            Some(retry) if response.status() == StatusCode::UNAUTHORIZED => {
+                let access_token = self.refresh(&access_token).await?;
+                check_status(self.http.send(retry.bearer_auth(access_token)).await?).await
+            }
+            _ => check_status(response).await,
+        }
+    }
+
+    /// Refreshes the access token that was rejected, unless another request already replaced it.
+    async fn refresh(&self, rejected_token: &str) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if token.access_token != rejected_token {
+            return Ok(token.access_token.clone());
+        }
+
+        let refresh_token = token.refresh_token.clone().ok_or_else(|| {
+            Error::Auth(format!("{} rejected the access token and it can't be refreshed, run `auth` to log in again", self.provider.name))
+        })?;
+        log::info!("{} access token was rejected, refreshing it", self.provider.name);
+        *token = self.provider.refresh_access_token(&refresh_token, &self.config, &self.store, &self.http).await?;
+        Ok(token.access_token.clone())
+    }
+}
@@ -1,109 +1,46 @@
-use oauth2::{
-    AuthorizationCode, AuthUrl, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, RedirectUrl,
-    Scope, TokenResponse, TokenUrl, RefreshToken,
-};
-use oauth2::reqwest::async_http_client;
-use oauth2::basic::BasicClient;
+use crate::error::Result;
+use crate::http::HttpClient;
+use crate::oauth::OAuthProvider;
+use crate::session::AuthSession;
+use crate::token_store::TokenStore;
 use crate::spotify::SpotifyClient;
-use std::error::Error;
-use std::fs::File;
-use std::io::{self, Read, Write};
-use std::time::{SystemTime, UNIX_EPOCH};
-
-fn store_tokens(access_token: &str, refresh_token: &str, expires_at: u64) -> io::Result<()> {
-    let mut file = File::create("spotify_tokens.txt")?;
-    file.write_all(format!("{}\n{}\n{}", access_token, refresh_token, expires_at).as_bytes())?;
-    Ok(())
-}
+use oauth2::url::Url;
+
+pub const SPOTIFY: OAuthProvider = OAuthProvider {
+    name: "Spotify",
+    auth_url: "https://accounts.spotify.com/authorize",
+    token_url: "https://accounts.spotify.com/api/token",
+    // Spotify has no revocation endpoint, access can only be removed from the account page
+    revocation_url: None,
+    device_authorization_url: None,
+    scopes: &[
+        "user-read-private",
+        "user-read-email",
+        "playlist-read-private",
+        "playlist-read-collaborative",
+        "playlist-modify-private",
+        "playlist-modify-public",
+    ],
+    token_key: "spotify",
+    legacy_token_file: "spotify_tokens.txt",
+};
 
-fn read_tokens() -> io::Result<(String, String, u64)> {
-    let mut file = File::open("spotify_tokens.txt")?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let tokens: Vec<&str> = contents.split('\n').collect();
-    if tokens.len() == 3 {
-        Ok((tokens[0].to_string(), tokens[1].to_string(), tokens[2].parse().unwrap()))
-    } else {
-        Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid token file format"))
-    }
+pub async fn authenticate(config: &crate::config::Config) -> Result<SpotifyClient> {
+    let store = TokenStore::open(&config.tokens)?;
+    let http = HttpClient::new(&config.http)?;
+    let token = SPOTIFY.authenticate(&config.spotify, &store, &http).await?;
+    Ok(SpotifyClient::new(AuthSession::new(&SPOTIFY, config.spotify.clone(), store, token, http)))
 }
 
-fn is_token_expired(expires_at: u64) -> bool {
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    now >= expires_at
+/// Starts a headless login, see [`OAuthProvider::start_login`].
+pub fn start_login(config: &crate::config::Config) -> Result<Url> {
+    let store = TokenStore::open(&config.tokens)?;
+    SPOTIFY.start_login(&config.spotify, &store)
 }
 
-pub async fn authenticate(config: &crate::config::Config) -> Result<SpotifyClient, Box<dyn Error>> {
-    if let Ok((access_token, refresh_token, expires_at)) = read_tokens() {
-        return if !is_token_expired(expires_at) {
-            Ok(SpotifyClient::new(access_token))
-        } else {
-            let new_access_token = refresh_access_token(&refresh_token, config).await?;
-            Ok(SpotifyClient::new(new_access_token))
-        }
-    }
-
-    let client = BasicClient::new(
-        ClientId::new(config.spotify.client_id.clone()),
-        Some(ClientSecret::new(config.spotify.client_secret.clone())),
-        AuthUrl::new("https://accounts.spotify.com/authorize".to_string())?,
-        Some(TokenUrl::new("https://accounts.spotify.com/api/token".to_string())?),
-    )
-    .set_redirect_uri(RedirectUrl::new(config.spotify.redirect_uri.clone())?);
-
-    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-    let (auth_url, csrf_token) = client
-        .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("user-read-private".to_string()))
-        .add_scope(Scope::new("user-read-email".to_string()))
-        .add_scope(Scope::new("playlist-read-private".to_string()))
-        .add_scope(Scope::new("playlist-read-collaborative".to_string()))
-        .add_scope(Scope::new("playlist-modify-private".to_string()))
-        .add_scope(Scope::new("playlist-modify-public".to_string()))
-        .set_pkce_challenge(pkce_challenge)
-        .url();
-
-    println!("Open this URL in your browser:\n{}", auth_url);
-    print!("Enter the URL you were redirected to: ");
-    io::stdout().flush()?;
-    let mut input_url = String::new();
-    io::stdin().read_line(&mut input_url)?;
-    let auth_code = input_url.split("code=").collect::<Vec<&str>>()[1].split("&").collect::<Vec<&str>>()[0];
-
-    let token_result = client
-        .exchange_code(AuthorizationCode::new(auth_code.parse().unwrap()))
-        .set_pkce_verifier(pkce_verifier)
-        .request_async(async_http_client)
-        .await?;
-
-    let access_token = token_result.access_token().secret().to_string();
-    let refresh_token = token_result.refresh_token().map(|token| token.secret().to_string()).unwrap_or_default();
-    let expires_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + token_result.expires_in().unwrap().as_secs();
-
-    store_tokens(&access_token, &refresh_token, expires_at)?;
-
-    Ok(SpotifyClient::new(access_token))
+/// Finishes a headless login, returning whether the redirect belonged to it.
+pub async fn finish_login(config: &crate::config::Config, redirect_url: &str) -> Result<bool> {
+    let store = TokenStore::open(&config.tokens)?;
+    let http = HttpClient::new(&config.http)?;
+    Ok(SPOTIFY.finish_login(&config.spotify, &store, &http, redirect_url).await?.is_some())
 }
-
-pub async fn refresh_access_token(refresh_token: &str, config: &crate::config::Config) -> Result<String, Box<dyn Error>> {
-    let client = BasicClient::new(
-        ClientId::new(config.spotify.client_id.clone()),
-        Some(ClientSecret::new(config.spotify.client_secret.clone())),
-        AuthUrl::new("https://accounts.spotify.com/authorize".to_string())?,
-        Some(TokenUrl::new("https://accounts.spotify.com/api/token".to_string())?),
-    )
-    .set_redirect_uri(RedirectUrl::new(config.spotify.redirect_uri.clone())?);
-
-    let token_result = client
-        .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
-        .request_async(async_http_client)
-        .await?;
-
-    let new_access_token = token_result.access_token().secret().to_string();
-    let new_refresh_token = token_result.refresh_token().map(|t| t.secret().to_string()).unwrap_or(refresh_token.to_string());
-    let expires_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + token_result.expires_in().unwrap().as_secs();
-
-    store_tokens(&new_access_token, &new_refresh_token, expires_at)?;
-
-    Ok(new_access_token)
-}
\ No newline at end of file
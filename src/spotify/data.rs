@@ -1,11 +1,10 @@
+use crate::error::{Error, Result};
 use crate::spotify::SpotifyClient;
-use reqwest::Client;
 use serde::{Serialize,Deserialize};
 use serde_json::Value;
 
-// TODO: Bypass 100 track limit with pagination, 
-
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct SpotifyUser {
     pub country: String,
     pub display_name: String,
@@ -23,29 +22,73 @@ pub struct SpotifyUser {
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct ExplicitContent {
     pub filter_enabled: bool,
     pub filter_locked: bool,
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct ExternalUrls {
     pub spotify: String,
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct Followers {
     pub href: Option<String>,
     pub total: u32,
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct Image {
     pub url: String,
     pub height: Option<u32>,
     pub width: Option<u32>,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct SpotifyTrack {
+    pub uri: String,
+    pub name: String,
+    pub duration_ms: u64,
+    pub explicit: bool,
+    /// Only present when searching with a market; `false` means the track can't be played there
+    #[serde(default)]
+    pub is_playable: Option<bool>,
+    pub artists: Vec<SpotifyArtist>,
+    pub album: SpotifyAlbum,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SpotifyArtist {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[allow(dead_code)]
+pub struct SpotifyAlbum {
+    pub name: String,
+    pub album_type: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpotifyPlaylistTrack {
+    /// `None` for items that can't be referenced by URI: tracks removed from the catalog, podcast
+    /// episodes and local files. They still take up a position in the playlist.
+    pub uri: Option<String>,
+    pub name: String,
+    pub isrc: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SpotifyPlaylistContents {
+    pub snapshot_id: String,
+    pub tracks: Vec<SpotifyPlaylistTrack>,
+}
+
 #[derive(Serialize)]
 struct CreatePlaylistRequest {
     name: String,
@@ -55,7 +98,7 @@ struct CreatePlaylistRequest {
 
 const SPOTIFY_API: &str = "https://api.spotify.com/v1";
 
-pub async fn create_playlist(client: &SpotifyClient, name: &str, description: &str, public: bool) -> Result<String, Box<dyn std::error::Error>> {
+pub async fn create_playlist(client: &SpotifyClient, name: &str, description: &str, public: bool) -> Result<String> {
     let user_id = get_current_user(client).await?.id;
     let url = format!("{}/users/{}/playlists", SPOTIFY_API, user_id);
     let request_body = CreatePlaylistRequest {
@@ -64,59 +107,129 @@ pub async fn create_playlist(client: &SpotifyClient, name: &str, description: &s
         public,
     };
 
-    let response = Client::new()
-        .post(&url)
-        .bearer_auth(&client.token)
-        .json(&request_body)
-        .send()
+    let response = client
+        .send(client.http().post(&url).json(&request_body))
         .await?
         .json::<Value>()
         .await?;
 
-    let playlist_id = response["id"].as_str().ok_or("Failed to get playlist ID")?.to_string();
+    let playlist_id = response["id"].as_str().ok_or_else(|| Error::Decode("Created playlist has no ID".to_string()))?.to_string();
     Ok(playlist_id)
 }
 
-pub async fn add_tracks_to_playlist(client: &SpotifyClient, playlist_id: &str, isrcs: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut track_uris = Vec::new();
-    for isrc in isrcs {
-        let track_uri = get_track_uri_from_isrc(client, &isrc).await?;
-        track_uris.push(track_uri);
+/// Spotify accepts at most this many tracks per add or remove request
+const MAX_TRACKS_PER_REQUEST: usize = 100;
+
+/// Inserts the tracks before the item at `position`, returning the playlist's new snapshot ID.
+///
+/// Tracks are sent in batches of 100, each placed right after the previous one. Batches after a
+/// failed one are not attempted, since their positions would no longer be correct; how far it got is logged.
+pub async fn add_tracks_to_playlist(client: &SpotifyClient, playlist_id: &str, track_uris: Vec<String>, position: usize) -> Result<String> {
+    let url = format!("{}/playlists/{}/tracks", SPOTIFY_API, playlist_id);
+    let batch_count = track_uris.len().div_ceil(MAX_TRACKS_PER_REQUEST);
+    let mut snapshot_id = String::new();
+
+    for (batch, chunk) in track_uris.chunks(MAX_TRACKS_PER_REQUEST).enumerate() {
+        let batch_position = position + batch * MAX_TRACKS_PER_REQUEST;
+        let request = client.http().post(&url).json(&serde_json::json!({ "uris": chunk, "position": batch_position }));
+        let response = client.send(request).await.inspect_err(|_| {
+            log::error!(
+                "Adding tracks stopped at batch {} of {} (tracks {}-{}), {} tracks were added before it",
+                batch + 1,
+                batch_count,
+                batch * MAX_TRACKS_PER_REQUEST + 1,
+                batch * MAX_TRACKS_PER_REQUEST + chunk.len(),
+                batch * MAX_TRACKS_PER_REQUEST,
+            )
+        })?;
+        snapshot_id = parse_snapshot_id(&response.json::<Value>().await?)?;
     }
 
+    Ok(snapshot_id)
+}
+
+/// Moves the item at `range_start` in front of the item at `insert_before`, returning the playlist's new snapshot ID.
+pub async fn reorder_playlist_tracks(client: &SpotifyClient, playlist_id: &str, snapshot_id: &str, range_start: usize, insert_before: usize) -> Result<String> {
     let url = format!("{}/playlists/{}/tracks", SPOTIFY_API, playlist_id);
-    let response = Client::new()
-        .post(&url)
-        .bearer_auth(&client.token)
-        .json(&serde_json::json!({ "uris": track_uris }))
-        .send()
-        .await?;
+    let request = client.http().put(&url).json(&serde_json::json!({
+        "range_start": range_start,
+        "insert_before": insert_before,
+        "range_length": 1,
+        "snapshot_id": snapshot_id,
+    }));
+    let response = client.send(request).await?;
+    parse_snapshot_id(&response.json::<Value>().await?)
+}
+
+/// Removes every occurrence of the given URIs, returning the playlist's new snapshot ID.
+pub async fn remove_tracks_from_playlist(client: &SpotifyClient, playlist_id: &str, snapshot_id: &str, track_uris: Vec<String>) -> Result<String> {
+    let url = format!("{}/playlists/{}/tracks", SPOTIFY_API, playlist_id);
+    let mut snapshot_id = snapshot_id.to_string();
 
-    if response.status().is_success() {
-        Ok(())
-    } else {
-        Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Failed to add tracks to playlist")))
+    for chunk in track_uris.chunks(MAX_TRACKS_PER_REQUEST) {
+        let tracks: Vec<Value> = chunk.iter().map(|uri| serde_json::json!({ "uri": uri })).collect();
+        let request = client.http().delete(&url).json(&serde_json::json!({ "tracks": tracks, "snapshot_id": snapshot_id }));
+        let response = client.send(request).await?;
+        snapshot_id = parse_snapshot_id(&response.json::<Value>().await?)?;
     }
+
+    Ok(snapshot_id)
+}
+
+/// Every change to a playlist returns its new snapshot ID, as does fetching it.
+fn parse_snapshot_id(response: &Value) -> Result<String> {
+    let snapshot_id = response["snapshot_id"].as_str().ok_or_else(|| Error::Decode("Response has no playlist snapshot ID".to_string()))?;
+    Ok(snapshot_id.to_string())
 }
 
-pub async fn fetch_spotify_playlist(client: &SpotifyClient, playlist_id: &str) -> Result<Value, Box<dyn std::error::Error>> {
+pub async fn fetch_spotify_playlist(client: &SpotifyClient, playlist_id: &str) -> Result<Value> {
     let url = format!("{}/playlists/{}", SPOTIFY_API, playlist_id);
-    let response = Client::new()
-        .get(&url)
-        .bearer_auth(&client.token)
-        .send()
+    let response = client
+        .send(client.http().get(&url))
         .await?
         .json::<Value>()
         .await?;
     Ok(response)
 }
 
-async fn get_current_user(client: &SpotifyClient) -> Result<SpotifyUser, Box<dyn std::error::Error>> {
+/// Fetches the playlist together with all of its tracks, following the `next` links past the first page.
+pub async fn fetch_playlist_contents(client: &SpotifyClient, playlist_id: &str) -> Result<SpotifyPlaylistContents> {
+    let playlist = fetch_spotify_playlist(client, playlist_id).await?;
+    let snapshot_id = parse_snapshot_id(&playlist)?;
+
+    let mut tracks = Vec::new();
+    let mut page = playlist["tracks"].clone();
+    loop {
+        for item in page["items"].as_array().unwrap_or(&vec![]) {
+            // Every item is kept so positions match Spotify's. Tracks removed from the catalog and
+            // episodes come back as null, and local files can't be removed by their URI.
+            let uri = item["track"]["uri"].as_str().filter(|uri| !uri.starts_with("spotify:local:"));
+            tracks.push(SpotifyPlaylistTrack {
+                uri: uri.map(str::to_string),
+                name: item["track"]["name"].as_str().unwrap_or_default().to_string(),
+                isrc: item["track"]["external_ids"]["isrc"].as_str().map(str::to_string),
+            });
+        }
+
+        match page["next"].as_str() {
+            Some(next_url) => {
+                page = client
+                    .send(client.http().get(next_url))
+                    .await?
+                    .json::<Value>()
+                    .await?;
+            }
+            None => break,
+        }
+    }
+
+    Ok(SpotifyPlaylistContents { snapshot_id, tracks })
+}
+
+pub async fn get_current_user(client: &SpotifyClient) -> Result<SpotifyUser> {
     let url = format!("{}/me", SPOTIFY_API);
-    let response = Client::new()
-        .get(url)
-        .bearer_auth(&client.token)
-        .send()
+    let response = client
+        .send(client.http().get(url))
         .await?
         .json::<SpotifyUser>()
         .await?;
@@ -124,19 +237,23 @@ async fn get_current_user(client: &SpotifyClient) -> Result<SpotifyUser, Box<dyn
     Ok(response)
 }
 
-async fn get_track_uri_from_isrc(client: &SpotifyClient, isrc: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let url = format!("{}/search?q=isrc:{}&type=track", SPOTIFY_API, isrc);
-    let response = Client::new()
+/// Searches for tracks in the market of the current user, so `is_playable` is filled in.
+pub async fn search_tracks(client: &SpotifyClient, query: &str, limit: u32) -> Result<Vec<SpotifyTrack>> {
+    let url = format!("{}/search", SPOTIFY_API);
+    let request = client.http()
         .get(&url)
-        .bearer_auth(&client.token)
-        .send()
+        .query(&[("q", query), ("type", "track"), ("limit", &limit.to_string()), ("market", "from_token")]);
+    let response = client
+        .send(request)
         .await?
         .json::<Value>()
         .await?;
 
-    let track_uri = response["tracks"]["items"][0]["uri"]
-        .as_str()
-        .ok_or("Failed to get track URI")?
-        .to_string();
-    Ok(track_uri)
-}
\ No newline at end of file
+    let tracks = response["tracks"]["items"]
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .filter_map(|item| serde_json::from_value(item.clone()).ok())
+        .collect();
+    Ok(tracks)
+}
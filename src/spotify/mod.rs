@@ -1,12 +1,27 @@
 pub mod auth;
 pub mod data;
 
+use crate::error::Result;
+use crate::http::HttpClient;
+use crate::session::AuthSession;
+use reqwest::{RequestBuilder, Response};
+
 pub struct SpotifyClient {
-    pub token: String, // Add other fields if necessary
+    session: AuthSession,
 }
 
 impl SpotifyClient {
-    pub fn new(token: String) -> Self {
-        Self { token }
+    pub fn new(session: AuthSession) -> Self {
+        Self { session }
+    }
+
+    /// Builds requests on the shared connection pool, to be sent with [`Self::send`].
+    pub fn http(&self) -> &HttpClient {
+        self.session.http()
+    }
+
+    /// Sends an authorized request, refreshing the access token if Spotify rejects it.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        self.session.send(request).await
     }
 }
@@ -1,24 +1,377 @@
-use crate::tidal::data::{fetch_playlists};
-use crate::spotify::data::{create_playlist, add_tracks_to_playlist};
+use crate::config::Config;
+use crate::db::{MatchMethod, SyncDatabase};
+use crate::diff::{diff_playlist, PlaylistChange};
+use crate::error::{Error, Result};
+use crate::matcher;
+use crate::plan::{print_playlist_plan, PlaylistPlan, SyncPlan};
+use crate::report::{print_failures, PlaylistFailure, SyncReport, UnmatchedTrack};
+use crate::tidal::data::{fetch_playlists, fetch_playlist_tracks, TidalPlaylist, TidalTrack};
+use crate::spotify::data::{create_playlist, add_tracks_to_playlist, fetch_playlist_contents, remove_tracks_from_playlist, reorder_playlist_tracks, search_tracks, fetch_spotify_playlist};
+use std::collections::HashMap;
 use crate::tidal::TidalClient;
 use crate::spotify::SpotifyClient;
 
-// TODO: Implement a local database to store the mapping between Tidal and Spotify tracks
-// We can use the Tidal last modified date to determine if a playlist has been updated
-// We should also skip tracks that are already in the Spotify playlist
+/// Syncs every changed playlist, or every playlist with `force`, returning the ones that failed. A
+/// failing playlist doesn't stop the others, except for authentication errors, which would fail all of them.
+pub async fn sync_data(
+    config: &Config,
+    tidal_client: &TidalClient,
+    spotify_client: &SpotifyClient,
+    selected: &[String],
+    dry_run: bool,
+    force: bool,
+) -> Result<Vec<PlaylistFailure>> {
+    let mut db = SyncDatabase::open()?;
+    let mut failures = Vec::new();
 
+    for playlist in changed_playlists(tidal_client, &mut db, selected, force).await? {
+        let name = playlist.name.clone();
+        let result = sync_playlist(config, tidal_client, spotify_client, &mut db, playlist, dry_run).await;
+        // Track lookups are worth keeping even on a dry run or when the playlist failed
+        db.save()?;
 
-pub async fn sync_data(
+        if let Err(error) = result {
+            record_failure(&mut failures, name, error)?;
+        }
+    }
+
+    if !dry_run {
+        write_report(&db)?;
+    }
+
+    Ok(failures)
+}
+
+async fn sync_playlist(
+    config: &Config,
     tidal_client: &TidalClient,
     spotify_client: &SpotifyClient,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let tidal_playlists = fetch_playlists(tidal_client).await?;
-    
-    for playlist in tidal_playlists {
-        let created_playlist_id = create_playlist(spotify_client, &playlist.name, "Automatically synced Tidal playlist", true).await?;
-        let tracks = playlist.tracks.iter().map(|track| track.attributes.isrc.clone()).collect();
-        add_tracks_to_playlist(spotify_client, &created_playlist_id, tracks).await?;
-    }
-    
+    db: &mut SyncDatabase,
+    playlist: TidalPlaylist,
+    dry_run: bool,
+) -> Result<()> {
+    let plan = plan_playlist(config, tidal_client, spotify_client, db, playlist).await?;
+    if dry_run {
+        print_playlist_plan(&plan);
+    } else {
+        db.set_unmatched_tracks(&plan.tidal_playlist_id, &plan.name, plan.unmatched.clone());
+        apply_plan(spotify_client, db, &plan).await?;
+    }
+    Ok(())
+}
+
+/// Adds the failure to `failures`, or returns the error if it would fail every other playlist too.
+///
+/// The run ends with that error, so the playlists that already failed are printed first.
+fn record_failure(failures: &mut Vec<PlaylistFailure>, name: String, error: Error) -> Result<()> {
+    if let Error::Auth(_) = error {
+        if !failures.is_empty() {
+            print_failures(failures);
+        }
+        return Err(error);
+    }
+    log::error!("Failed to sync '{}': {}", name, error);
+    failures.push(PlaylistFailure { name, error });
     Ok(())
 }
+
+/// Plans the sync of every changed playlist, or every playlist with `force`, and writes it to `path` for review, without touching Spotify.
+///
+/// Playlists that fail to plan are left out of the plan and returned.
+pub async fn write_sync_plan(
+    config: &Config,
+    tidal_client: &TidalClient,
+    spotify_client: &SpotifyClient,
+    selected: &[String],
+    path: &str,
+    force: bool,
+) -> Result<Vec<PlaylistFailure>> {
+    let mut db = SyncDatabase::open()?;
+    let mut playlists = Vec::new();
+    let mut failures = Vec::new();
+
+    for playlist in changed_playlists(tidal_client, &mut db, selected, force).await? {
+        let name = playlist.name.clone();
+        let result = plan_playlist(config, tidal_client, spotify_client, &mut db, playlist).await;
+        if let Ok(plan) = &result {
+            db.set_unmatched_tracks(&plan.tidal_playlist_id, &plan.name, plan.unmatched.clone());
+        }
+        db.save()?;
+
+        match result {
+            Ok(plan) => playlists.push(plan),
+            Err(error) => record_failure(&mut failures, name, error)?,
+        }
+    }
+
+    let plan = SyncPlan::new(playlists);
+    plan.print();
+    plan.save(path)?;
+    println!("Plan written to {}", path);
+    write_report(&db)?;
+    Ok(failures)
+}
+
+/// Applies a plan written by [`write_sync_plan`]. Nothing is changed if any of the Spotify playlists
+/// were modified since the plan was made, as the planned positions would no longer be right.
+///
+/// Once checked, a playlist failing to apply doesn't stop the others; the failed ones are returned.
+pub async fn apply_sync_plan(spotify_client: &SpotifyClient, path: &str) -> Result<Vec<PlaylistFailure>> {
+    let mut db = SyncDatabase::open()?;
+    let plan = SyncPlan::load(path)?;
+
+    for playlist in &plan.playlists {
+        match &playlist.spotify_playlist_id {
+            Some(spotify_playlist_id) => {
+                let current = fetch_spotify_playlist(spotify_client, spotify_playlist_id).await?;
+                if current["snapshot_id"].as_str() != playlist.snapshot_id.as_deref() {
+                    return Err(Error::PlanOutdated { playlist: playlist.name.clone() });
+                }
+            }
+            None => {
+                if db.get_playlist(&playlist.tidal_playlist_id).is_some() {
+                    return Err(Error::PlanOutdated { playlist: playlist.name.clone() });
+                }
+            }
+        }
+    }
+
+    let mut failures = Vec::new();
+    for playlist in &plan.playlists {
+        match apply_plan(spotify_client, &mut db, playlist).await {
+            Ok(()) => println!("Applied plan for '{}'", playlist.name),
+            Err(error) => record_failure(&mut failures, playlist.name.clone(), error)?,
+        }
+    }
+    Ok(failures)
+}
+
+/// Fetches the selected Tidal playlists (all if none are selected), leaving out those unchanged
+/// since their last sync unless `force` is set.
+///
+/// When all playlists are fetched, the unmatched tracks of playlists deleted on Tidal are forgotten.
+async fn changed_playlists(tidal_client: &TidalClient, db: &mut SyncDatabase, selected: &[String], force: bool) -> Result<Vec<TidalPlaylist>> {
+    let playlists = fetch_selected_playlists(tidal_client, selected).await?;
+    if selected.is_empty() {
+        db.retain_unmatched_tracks(|tidal_playlist_id| playlists.iter().any(|playlist| playlist.id == tidal_playlist_id));
+    }
+    if force {
+        return Ok(playlists);
+    }
+    Ok(playlists
+        .into_iter()
+        .filter(|playlist| {
+            let unchanged = db.is_playlist_unchanged(&playlist.id, playlist.last_modified_at.as_deref(), playlist.number_of_items);
+            if unchanged {
+                log::info!("Skipping unchanged playlist '{}'", playlist.name);
+            }
+            !unchanged
+        })
+        .collect())
+}
+
+/// Fetches the Tidal playlists matching any of the given names or ids, or all of them if none are given.
+pub async fn fetch_selected_playlists(tidal_client: &TidalClient, selected: &[String]) -> Result<Vec<TidalPlaylist>> {
+    let playlists = fetch_playlists(tidal_client).await?;
+    if selected.is_empty() {
+        return Ok(playlists);
+    }
+
+    for name in selected {
+        if !playlists.iter().any(|playlist| playlist.id == *name || playlist.name == *name) {
+            log::warn!("No Tidal playlist named '{}'", name);
+        }
+    }
+    Ok(playlists
+        .into_iter()
+        .filter(|playlist| selected.iter().any(|name| playlist.id == *name || playlist.name == *name))
+        .collect())
+}
+
+/// Writes the unmatched tracks of all playlists, not just the ones synced in this run.
+fn write_report(db: &SyncDatabase) -> Result<()> {
+    let report = SyncReport::from_database(db);
+    report.write()?;
+    if report.unmatched_count() > 0 {
+        println!("{} tracks could not be matched, see unmatched_tracks.txt", report.unmatched_count());
+    }
+    Ok(())
+}
+
+/// Fetches the playlist from Tidal, matches its tracks on Spotify and works out the changes needed,
+/// without modifying anything on Spotify.
+pub async fn plan_playlist(
+    config: &Config,
+    tidal_client: &TidalClient,
+    spotify_client: &SpotifyClient,
+    db: &mut SyncDatabase,
+    mut playlist: TidalPlaylist,
+) -> Result<PlaylistPlan> {
+    fetch_playlist_tracks(tidal_client, &mut playlist).await?;
+
+    let mut spotify_playlist_id = db.get_playlist(&playlist.id).map(|link| link.spotify_playlist_id.clone());
+    let existing = match &spotify_playlist_id {
+        Some(id) => match fetch_playlist_contents(spotify_client, id).await {
+            Ok(contents) => Some(contents),
+            // The linked playlist is gone, so sync to a new one as if it was never synced
+            Err(Error::NotFound { .. }) => {
+                log::warn!("Spotify playlist {} synced from '{}' no longer exists, a new one will be created", id, playlist.name);
+                db.unlink_playlist(&playlist.id);
+                spotify_playlist_id = None;
+                None
+            }
+            Err(e) => return Err(e),
+        },
+        None => None,
+    };
+    let existing_tracks = existing.as_ref().map(|existing| existing.tracks.as_slice()).unwrap_or_default();
+    let existing_by_isrc: HashMap<&str, &str> = existing_tracks
+        .iter()
+        .filter_map(|track| Some((track.isrc.as_deref()?, track.uri.as_deref()?)))
+        .collect();
+
+    let mut track_names: HashMap<String, String> = existing_tracks
+        .iter()
+        .filter_map(|track| Some((track.uri.clone()?, track.name.clone())))
+        .collect();
+    let mut desired_uris = Vec::new();
+    let mut unmatched = Vec::new();
+    for track in &playlist.tracks {
+        // Matching on ISRC first avoids a search for tracks we can already see in the playlist
+        let track_uri = match existing_by_isrc.get(track.attributes.isrc.as_str()) {
+            Some(uri) => Some(uri.to_string()),
+            None => resolve_track_uri(spotify_client, db, track).await?,
+        };
+        match track_uri {
+            Some(track_uri) => {
+                track_names.entry(track_uri.clone()).or_insert_with(|| describe_track(track));
+                desired_uris.push(track_uri);
+            }
+            None => unmatched.push(UnmatchedTrack {
+                tidal_id: track.id.clone(),
+                title: track.attributes.title.clone(),
+                artists: track.artists.clone(),
+                isrc: track.attributes.isrc.clone(),
+                reason: format!(
+                    "No Spotify track with this ISRC and no metadata match with confidence of at least {}",
+                    matcher::MIN_CONFIDENCE
+                ),
+            }),
+        }
+    }
+
+    if !unmatched.is_empty() {
+        log::warn!("{} tracks of '{}' could not be matched on Spotify", unmatched.len(), playlist.name);
+    }
+
+    let current_uris: Vec<Option<String>> = existing_tracks.iter().map(|track| track.uri.clone()).collect();
+    let mode = config.sync.mode_for(&playlist.id, &playlist.name);
+    let changes = diff_playlist(&current_uris, &desired_uris, mode);
+
+    Ok(PlaylistPlan {
+        tidal_playlist_id: playlist.id,
+        name: playlist.name,
+        spotify_playlist_id,
+        snapshot_id: existing.map(|existing| existing.snapshot_id),
+        changes,
+        unmatched,
+        track_names,
+        last_modified_at: playlist.last_modified_at,
+        number_of_items: playlist.number_of_items,
+    })
+}
+
+/// Creates the Spotify playlist if needed, applies the planned changes and records the sync.
+pub async fn apply_plan(
+    spotify_client: &SpotifyClient,
+    db: &mut SyncDatabase,
+    plan: &PlaylistPlan,
+) -> Result<()> {
+    let spotify_playlist_id = match &plan.spotify_playlist_id {
+        Some(spotify_playlist_id) => spotify_playlist_id.clone(),
+        None => {
+            let created_playlist_id = create_playlist(spotify_client, &plan.name, "Automatically synced Tidal playlist", true).await?;
+            // Remember the link right away so a failure further down doesn't lead to a duplicate next run
+            db.link_playlist(&plan.tidal_playlist_id, &plan.name, &created_playlist_id);
+            db.save()?;
+            created_playlist_id
+        }
+    };
+
+    apply_changes(spotify_client, &spotify_playlist_id, plan.snapshot_id.as_deref().unwrap_or_default(), plan.changes.clone()).await?;
+
+    // Playlists with unmatched tracks aren't recorded as up to date, so those tracks are searched for again next run
+    let last_modified_at = if plan.unmatched.is_empty() { plan.last_modified_at.as_deref() } else { None };
+    db.mark_playlist_synced(&plan.tidal_playlist_id, last_modified_at, plan.number_of_items);
+    db.save()?;
+    Ok(())
+}
+
+fn describe_track(track: &TidalTrack) -> String {
+    match track.artists.first() {
+        Some(artist) => format!("{} - {}", track.attributes.title, artist),
+        None => track.attributes.title.clone(),
+    }
+}
+
+/// Applies the changes in order, threading the snapshot ID returned by each request into the next.
+async fn apply_changes(
+    spotify_client: &SpotifyClient,
+    playlist_id: &str,
+    snapshot_id: &str,
+    changes: Vec<PlaylistChange>,
+) -> Result<String> {
+    let mut snapshot_id = snapshot_id.to_string();
+    for change in changes {
+        snapshot_id = match change {
+            PlaylistChange::Remove { uris } => remove_tracks_from_playlist(spotify_client, playlist_id, &snapshot_id, uris).await?,
+            PlaylistChange::Add { uris, position } => add_tracks_to_playlist(spotify_client, playlist_id, uris, position).await?,
+            PlaylistChange::Move { range_start, insert_before } => {
+                reorder_playlist_tracks(spotify_client, playlist_id, &snapshot_id, range_start, insert_before).await?
+            }
+        };
+    }
+    Ok(snapshot_id)
+}
+
+/// Looks the track up in the local database first and only searches Spotify on a miss.
+///
+/// The best ranked ISRC search result is used if there is one; if Spotify has nothing under that
+/// ISRC, the track is matched on its metadata instead and low-confidence matches are logged for
+/// review. Returns `None` if neither finds a match.
+async fn resolve_track_uri(
+    spotify_client: &SpotifyClient,
+    db: &mut SyncDatabase,
+    track: &TidalTrack,
+) -> Result<Option<String>> {
+    if let Some(mapping) = db.get_track(&track.id) {
+        return Ok(Some(mapping.spotify_uri.clone()));
+    }
+
+    let isrc = &track.attributes.isrc;
+    let candidates = search_tracks(spotify_client, &format!("isrc:{}", isrc), 10).await?;
+    let rankings = matcher::rank_isrc_candidates(track, &candidates);
+    if let Some(best) = rankings.first() {
+        for (rank, ranking) in rankings.iter().enumerate() {
+            log::debug!("ISRC {} candidate #{}: {}", isrc, rank + 1, ranking);
+        }
+        db.insert_track(&track.id, isrc, &best.uri, MatchMethod::Isrc, None);
+        return Ok(Some(best.uri.clone()));
+    }
+
+    for query in matcher::search_queries(track) {
+        let candidates = search_tracks(spotify_client, &query, 10).await?;
+        if let Some(found) = matcher::best_match(track, &candidates) {
+            if found.confidence < matcher::LOW_CONFIDENCE {
+                log::warn!(
+                    "Low confidence match ({:.2}) for '{}' ({}): {}",
+                    found.confidence, track.attributes.title, isrc, found.uri
+                );
+            }
+            db.insert_track(&track.id, isrc, &found.uri, MatchMethod::Fuzzy, Some(found.confidence));
+            return Ok(Some(found.uri));
+        }
+    }
+
+    Ok(None)
+}
@@ -1,110 +1,46 @@
+use crate::error::Result;
+use crate::http::HttpClient;
+use crate::oauth::OAuthProvider;
+use crate::session::AuthSession;
+use crate::token_store::TokenStore;
 use crate::tidal::TidalClient;
-use oauth2::basic::BasicClient;
-use oauth2::reqwest::async_http_client;
-use oauth2::RefreshToken;
-use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
-    PkceCodeChallenge, RedirectUrl, Scope, TokenResponse,
-    TokenUrl,
+use oauth2::url::Url;
+
+pub const TIDAL: OAuthProvider = OAuthProvider {
+    name: "Tidal",
+    auth_url: "https://login.tidal.com/authorize",
+    token_url: "https://auth.tidal.com/v1/oauth2/token",
+    revocation_url: Some("https://auth.tidal.com/v1/oauth2/revoke"),
+    device_authorization_url: Some("https://auth.tidal.com/v1/oauth2/device_authorization"),
+    scopes: &["playlists.read", "collection.read", "user.read", "recommendations.read"],
+    token_key: "tidal",
+    legacy_token_file: "tidal_tokens.txt",
 };
-use std::error::Error;
-use std::fs::File;
-use std::io::{self, Read, Write};
-use std::time::{SystemTime, UNIX_EPOCH};
 
-fn store_tokens(access_token: &str, refresh_token: &str, expires_at: u64) -> io::Result<()> {
-    let mut file = File::create("tidal_tokens.txt")?;
-    file.write_all(format!("{}\n{}\n{}", access_token, refresh_token, expires_at).as_bytes())?;
-    Ok(())
+pub async fn authenticate(config: &crate::config::Config) -> Result<TidalClient> {
+    let store = TokenStore::open(&config.tokens)?;
+    let http = HttpClient::new(&config.http)?;
+    let token = TIDAL.authenticate(&config.tidal, &store, &http).await?;
+    Ok(TidalClient::new(AuthSession::new(&TIDAL, config.tidal.clone(), store, token, http)))
 }
 
-fn read_tokens() -> io::Result<(String, String, u64)> {
-    let mut file = File::open("tidal_tokens.txt")?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let tokens: Vec<&str> = contents.split('\n').collect();
-    if tokens.len() == 3 {
-        Ok((tokens[0].to_string(), tokens[1].to_string(), tokens[2].parse().unwrap()))
-    } else {
-        Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid token file format"))
-    }
+/// Logs in with a code entered on another device, for machines without a browser.
+pub async fn authenticate_device(config: &crate::config::Config) -> Result<TidalClient> {
+    let store = TokenStore::open(&config.tokens)?;
+    let http = HttpClient::new(&config.http)?;
+    let token = TIDAL.authenticate_device(&config.tidal, &store, &http).await?;
+    Ok(TidalClient::new(AuthSession::new(&TIDAL, config.tidal.clone(), store, token, http)))
 }
 
-fn is_token_expired(expires_at: u64) -> bool {
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    now >= expires_at
+/// Starts a headless login, see [`OAuthProvider::start_login`].
+pub fn start_login(config: &crate::config::Config) -> Result<Url> {
+    let store = TokenStore::open(&config.tokens)?;
+    TIDAL.start_login(&config.tidal, &store)
 }
 
-pub async fn authenticate(config: &crate::config::Config) -> Result<TidalClient, Box<dyn Error>> {
-    if let Ok((access_token, refresh_token, expires_at)) = read_tokens() {
-        return if !is_token_expired(expires_at) {
-            Ok(TidalClient::new(access_token))
-        } else {
-            let new_access_token = refresh_access_token(&refresh_token, config).await?;
-            Ok(TidalClient::new(new_access_token))
-        }
-    }
-
-    // TODO: Resolve duplicate code
-    let client = BasicClient::new(
-        ClientId::new(config.tidal.client_id.clone()),
-        Some(ClientSecret::new(config.tidal.client_secret.clone())),
-        AuthUrl::new("https://login.tidal.com/authorize".to_string())?,
-        Some(TokenUrl::new("https://auth.tidal.com/v1/oauth2/token".to_string())?),
-    )
-        .set_redirect_uri(RedirectUrl::new(config.tidal.redirect_uri.clone())?);
-
-    let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
-    let (auth_url, _csrf_state) = client
-        .authorize_url(CsrfToken::new_random)
-        .set_pkce_challenge(pkce_code_challenge)
-        .add_scope(Scope::new("playlists.read".to_string()))
-        .add_scope(Scope::new("collection.read".to_string()))
-        .add_scope(Scope::new("user.read".to_string()))
-        .add_scope(Scope::new("recommendations.read".to_string()))
-        .url();
-
-    println!("Open this URL in your browser:\n{}", auth_url);
-    println!("Enter the exact url after authorization:");
-    let mut auth_code = String::new();
-    io::stdin().read_line(&mut auth_code)?;
-
-    let auth_code = auth_code.split("code=").collect::<Vec<&str>>()[1].split("&").collect::<Vec<&str>>()[0];
-    let token_result = client
-        .exchange_code(AuthorizationCode::new(auth_code.parse().unwrap()))
-        .set_pkce_verifier(pkce_code_verifier)
-        .request_async(async_http_client)
-        .await?;
-
-    let access_token = token_result.access_token().secret().clone();
-    let refresh_token = token_result.refresh_token().map(|t| t.secret().clone()).unwrap_or_default();
-    let expires_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + token_result.expires_in().unwrap().as_secs();
-
-    store_tokens(&access_token, &refresh_token, expires_at)?;
-
-    Ok(TidalClient::new(access_token))
+/// Finishes a headless login, returning whether the redirect belonged to it.
+pub async fn finish_login(config: &crate::config::Config, redirect_url: &str) -> Result<bool> {
+    let store = TokenStore::open(&config.tokens)?;
+    let http = HttpClient::new(&config.http)?;
+    Ok(TIDAL.finish_login(&config.tidal, &store, &http, redirect_url).await?.is_some())
 }
-
-pub async fn refresh_access_token(refresh_token: &str, config: &crate::config::Config) -> Result<String, Box<dyn Error>> {
-    // TODO: Resolve duplicate code
-    let client = BasicClient::new(
-        ClientId::new(config.tidal.client_id.clone()),
-        Some(ClientSecret::new(config.tidal.client_secret.clone())),
-        AuthUrl::new("https://login.tidal.com/authorize".to_string())?,
-        Some(TokenUrl::new("https://auth.tidal.com/v1/oauth2/token".to_string())?),
-    )
-    .set_redirect_uri(RedirectUrl::new(config.tidal.redirect_uri.clone())?);
-
-    let token_result = client
-        .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
-        .request_async(async_http_client)
-        .await?;
-
-    let new_access_token = token_result.access_token().secret().clone();
-    let new_refresh_token = token_result.refresh_token().map(|t| t.secret().clone()).unwrap_or(refresh_token.to_string());
-    let expires_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + token_result.expires_in().unwrap().as_secs();
-
-    store_tokens(&new_access_token, &new_refresh_token, expires_at)?;
-
-    Ok(new_access_token)
-}
\ No newline at end of file
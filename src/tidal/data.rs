@@ -1,6 +1,7 @@
-use reqwest::Client;
+use reqwest::Response;
 use reqwest::header::HeaderMap;
 use serde::Deserialize;
+use crate::error::{Error, Result};
 use crate::tidal::TidalClient;
 use serde_json::Value;
 use tokio::time::{sleep, Duration};
@@ -9,18 +10,29 @@ use tokio::time::{sleep, Duration};
 pub struct TidalPlaylist {
     pub id: String,
     pub name: String,
+    pub last_modified_at: Option<String>,
+    pub number_of_items: Option<u32>,
+    pub items_url: String,
     pub tracks: Vec<TidalTrack>,
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct TidalTrack {
     pub id: String,
     pub attributes: TrackAttributes,
     pub relationships: TrackRelationships,
     pub links: TrackLinks,
+    /// Artist names, resolved from the `included` resources of the track details response
+    #[serde(skip)]
+    pub artists: Vec<String>,
+    /// Album title, resolved from the `included` resources of the track details response
+    #[serde(skip)]
+    pub album: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct TrackAttributes {
     pub title: String,
     pub isrc: String,
@@ -36,17 +48,20 @@ pub struct TrackAttributes {
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct ExternalLink {
     pub href: String,
     pub meta: ExternalLinkMeta,
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct ExternalLinkMeta {
     pub r#type: String,
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct TrackRelationships {
     pub albums: RelationshipLinks,
     pub artists: RelationshipLinks,
@@ -57,132 +72,193 @@ pub struct TrackRelationships {
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct RelationshipLinks {
     pub links: RelationshipSelfLink,
+    #[serde(default)]
+    pub data: Vec<ResourceIdentifier>,
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct ResourceIdentifier {
+    pub id: String,
+    pub r#type: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct RelationshipSelfLink {
     #[serde(rename = "self")]
     pub self_link: String,
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct TrackLinks {
     #[serde(rename = "self")]
     pub self_link: String,
 }
 
+impl TrackAttributes {
+    /// Track duration in seconds, parsed from the ISO 8601 duration Tidal returns (e.g. `PT3M25S`).
+    pub fn duration_secs(&self) -> Option<u64> {
+        let time = self.duration.strip_prefix("PT")?;
+        let mut seconds = 0.0;
+        let mut number = String::new();
+        for c in time.chars() {
+            match c {
+                'H' => seconds += number.parse::<f64>().ok()? * 3600.0,
+                'M' => seconds += number.parse::<f64>().ok()? * 60.0,
+                'S' => seconds += number.parse::<f64>().ok()?,
+                _ => {
+                    number.push(c);
+                    continue;
+                }
+            }
+            number.clear();
+        }
+        Some(seconds.round() as u64)
+    }
+}
+
 const TIDAL_API: &str = "https://openapi.tidal.com/v2";
 
-pub async fn fetch_playlists(client: &TidalClient) -> Result<Vec<TidalPlaylist>, Box<dyn std::error::Error>> {
-    let response = Client::new()
-        .get(format!("{}/playlists/me", TIDAL_API))
-        .bearer_auth(&client.token)
-        .send()
+/// The name of the logged in user, from their profile.
+pub async fn fetch_user_name(client: &TidalClient) -> Result<String> {
+    let response_json = client
+        .send(client.http().get(format!("{}/users/me", TIDAL_API)))
+        .await?
+        .json::<Value>()
         .await?;
 
-    if response.status().is_success() {
-        let response_headers = response.headers().clone();
-        let response_body = response.text().await?;
-        if response_body.is_empty() {
-            return Err("Empty response body".into());
-        }
+    let user = &response_json["data"];
+    let name = user["attributes"]["username"]
+        .as_str()
+        .or(user["id"].as_str())
+        .ok_or_else(|| Error::Decode("User profile has no name or ID".to_string()))?;
+    Ok(name.to_string())
+}
 
-        let response_json: Value = serde_json::from_str(&response_body)?;
-
-        let mut playlists = Vec::new();
-        let mut remaining_tokens = get_remaining_tokens(&response_headers);
-        let replenish_rate = get_replenish_rate(&response_headers);
-        let _burst_capacity = get_burst_capacity(&response_headers);
-
-        if let Some(data) = response_json["data"].as_array() {
-            for playlist in data {
-                let id = playlist["id"].as_str().unwrap_or_default().to_string();
-                let name = playlist["attributes"]["name"].as_str().unwrap_or_default().to_string();
-                let mut items_url = playlist["relationships"]["items"]["links"]["self"].as_str().unwrap_or_default().to_string();
-                let mut tracks = Vec::new();
-
-                loop {
-                    // Check if we have enough tokens, if not, wait
-                    while remaining_tokens <= 1 {
-                        sleep(Duration::from_secs(3)).await;
-                        remaining_tokens = remaining_tokens + replenish_rate;
-                    }
-
-                    let items_response = Client::new()
-                        .get(format!("{}{}", TIDAL_API, &items_url))
-                        .bearer_auth(&client.token)
-                        .send()
-                        .await?;
-
-                    if items_response.status().is_success() {
-                        let items_response_headers = items_response.headers().clone();
-                        let items_response_body = items_response.text().await?;
-                        if items_response_body.is_empty() {
-                            return Err("Empty items response body".into());
-                        }
-
-                        let items_response_json: Value = serde_json::from_str(&items_response_body)?;
-                        let track_ids: Vec<String> = items_response_json["data"]
-                            .as_array()
-                            .unwrap_or(&vec![])
-                            .iter()
-                            .map(|item| item["id"].as_str().unwrap_or_default().to_string())
-                            .collect();
-
-                        // Fetch track details
-                        let track_details = fetch_track_details(client, track_ids, "US").await?;
-                        tracks.extend(track_details);
-                        remaining_tokens -= get_requested_tokens(&items_response_headers);
-
-                        if let Some(next_url) = items_response_json["links"]["next"].as_str() {
-                            items_url = next_url.to_string();
-                        } else {
-                            break;
-                        }
-                    } else {
-                        return Err(format!("Failed to fetch items: {}", items_response.status()).into());
-                    }
-                }
+pub async fn fetch_playlists(client: &TidalClient) -> Result<Vec<TidalPlaylist>> {
+    let response = client
+        .send(client.http().get(format!("{}/playlists/me", TIDAL_API)))
+        .await?;
 
-                playlists.push(TidalPlaylist { id, name, tracks });
-            }
-        }
+    let response_headers = response.headers().clone();
+    let response_json = parse_body(response).await?;
 
-        Ok(playlists)
-    } else {
-        Err(format!("Failed to fetch playlists: {}", response.status()).into())
+    let mut playlists = Vec::new();
+    {
+        let mut rate_limit = client.rate_limit.lock().await;
+        rate_limit.remaining_tokens = get_remaining_tokens(&response_headers);
+        rate_limit.replenish_rate = get_replenish_rate(&response_headers);
     }
+    let _burst_capacity = get_burst_capacity(&response_headers);
+
+    if let Some(data) = response_json["data"].as_array() {
+        for playlist in data {
+            let attributes = &playlist["attributes"];
+            playlists.push(TidalPlaylist {
+                id: playlist["id"].as_str().unwrap_or_default().to_string(),
+                name: attributes["name"].as_str().unwrap_or_default().to_string(),
+                last_modified_at: attributes["lastModifiedAt"].as_str().map(str::to_string),
+                number_of_items: attributes["numberOfItems"].as_u64().map(|count| count as u32),
+                items_url: playlist["relationships"]["items"]["links"]["self"].as_str().unwrap_or_default().to_string(),
+                tracks: Vec::new(),
+            });
+        }
+    }
+
+    Ok(playlists)
 }
 
-pub async fn fetch_track_details(client: &TidalClient, track_ids: Vec<String>, country_code: &str) -> Result<Vec<TidalTrack>, Box<dyn std::error::Error>> {
-    let response = Client::new()
-        .get(format!("{}/tracks", TIDAL_API))
-        .query(&[("countryCode", country_code), ("filter[id]", &*track_ids.join(","))])
-        .bearer_auth(&client.token)
-        .send()
-        .await?;
+/// Fetches every item of the playlist into `playlist.tracks`, following the pagination links.
+pub async fn fetch_playlist_tracks(client: &TidalClient, playlist: &mut TidalPlaylist) -> Result<()> {
+    let mut items_url = playlist.items_url.clone();
+    let mut tracks = Vec::new();
 
-    if response.status().is_success() {
-        let response_body = response.text().await?;
-        if response_body.is_empty() {
-            return Err("Empty response body".into());
+    loop {
+        // Check if we have enough tokens, if not, wait
+        {
+            let mut rate_limit = client.rate_limit.lock().await;
+            while rate_limit.remaining_tokens <= 1 {
+                sleep(Duration::from_secs(3)).await;
+                rate_limit.remaining_tokens += rate_limit.replenish_rate;
+            }
         }
 
-        let response_json: Value = serde_json::from_str(&response_body)?;
-        let tracks: Vec<TidalTrack> = response_json["data"]
+        let items_response = client
+            .send(client.http().get(format!("{}{}", TIDAL_API, &items_url)))
+            .await?;
+
+        let items_response_headers = items_response.headers().clone();
+        let items_response_json = parse_body(items_response).await?;
+        let track_ids: Vec<String> = items_response_json["data"]
             .as_array()
             .unwrap_or(&vec![])
             .iter()
-            .map(|item| serde_json::from_value(item.clone()).unwrap())
+            .map(|item| item["id"].as_str().unwrap_or_default().to_string())
             .collect();
 
-        Ok(tracks)
-    } else {
-        Err(format!("Failed to fetch track details: {}", response.status()).into())
+        // Fetch track details
+        let track_details = fetch_track_details(client, track_ids, "US").await?;
+        tracks.extend(track_details);
+        client.rate_limit.lock().await.remaining_tokens -= get_requested_tokens(&items_response_headers);
+
+        if let Some(next_url) = items_response_json["links"]["next"].as_str() {
+            items_url = next_url.to_string();
+        } else {
+            break;
+        }
+    }
+
+    playlist.tracks = tracks;
+    Ok(())
+}
+
+pub async fn fetch_track_details(client: &TidalClient, track_ids: Vec<String>, country_code: &str) -> Result<Vec<TidalTrack>> {
+    let request = client.http()
+        .get(format!("{}/tracks", TIDAL_API))
+        .query(&[("countryCode", country_code), ("filter[id]", &*track_ids.join(",")), ("include", "artists,albums")]);
+    let response_json = parse_body(client.send(request).await?).await?;
+
+    let included = response_json["included"].as_array().cloned().unwrap_or_default();
+    let included_attribute = |resource: &ResourceIdentifier, attribute: &str| {
+        included
+            .iter()
+            .find(|item| item["type"] == resource.r#type.as_str() && item["id"] == resource.id.as_str())
+            .and_then(|item| item["attributes"][attribute].as_str())
+            .map(str::to_string)
+    };
+
+    let mut tracks = response_json["data"]
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .map(|item| serde_json::from_value(item.clone()))
+        .collect::<std::result::Result<Vec<TidalTrack>, _>>()?;
+    for track in &mut tracks {
+        track.artists = track.relationships.artists.data.iter().filter_map(|artist| included_attribute(artist, "name")).collect();
+        track.album = track.relationships.albums.data.first().and_then(|album| included_attribute(album, "title"));
+    }
+
+    // The filter doesn't preserve the order of the ids we asked for, which is the playlist order
+    tracks.sort_by_key(|track| track_ids.iter().position(|id| *id == track.id));
+
+    Ok(tracks)
+}
+
+/// Parses a JSON response body, an empty one is an error rather than an empty result.
+async fn parse_body(response: Response) -> Result<Value> {
+    let url = response.url().to_string();
+    let body = response.text().await?;
+    if body.is_empty() {
+        return Err(Error::Decode(format!("Empty response body from {}", url)));
     }
+    Ok(serde_json::from_str(&body)?)
 }
+
 fn get_remaining_tokens(headers: &HeaderMap) -> i32 {
     headers.get("X-RateLimit-Remaining")
         .and_then(|value| value.to_str().ok())
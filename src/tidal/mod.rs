@@ -1,12 +1,41 @@
 pub mod auth;
 pub mod data;
 
+use crate::error::Result;
+use crate::http::HttpClient;
+use crate::session::AuthSession;
+use reqwest::{RequestBuilder, Response};
+use tokio::sync::Mutex;
+
 pub struct TidalClient {
-    pub token: String,
+    session: AuthSession,
+    pub(crate) rate_limit: Mutex<RateLimit>,
 }
 
 impl TidalClient {
-    pub fn new(token: String) -> Self {
-        Self { token }
+    pub fn new(session: AuthSession) -> Self {
+        Self { session, rate_limit: Mutex::new(RateLimit::default()) }
+    }
+
+    /// Builds requests on the shared connection pool, to be sent with [`Self::send`].
+    pub fn http(&self) -> &HttpClient {
+        self.session.http()
+    }
+
+    /// Sends an authorized request, refreshing the access token if Tidal rejects it.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        self.session.send(request).await
+    }
+}
+
+/// Token bucket state reported by the Tidal API through its X-RateLimit-* headers.
+pub(crate) struct RateLimit {
+    pub remaining_tokens: i32,
+    pub replenish_rate: i32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self { remaining_tokens: 2, replenish_rate: 1 }
     }
 }
@@ -0,0 +1,193 @@
+use crate::config::TokenConfig;
+use crate::db::now;
+use crate::error::{Error, Result};
+use crate::vault;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// OAuth tokens of one service as persisted between runs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredToken {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub token_type: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Unix timestamp after which the access token is no longer valid, `None` if the provider didn't say
+    pub expires_at: Option<u64>,
+    pub obtained_at: u64,
+}
+
+impl StoredToken {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| now() >= expires_at)
+    }
+}
+
+/// A login started with `auth start` that is waiting for the redirect URL to be passed to `auth finish`.
+#[derive(Serialize, Deserialize)]
+pub struct PendingLogin {
+    /// The `state` parameter sent with the authorization request
+    pub csrf_state: String,
+    pub pkce_verifier: String,
+    pub started_at: u64,
+}
+
+/// Token files in the per-user configuration directory, one JSON file per service, readable only by the user.
+///
+/// With `encrypt` set, files are written to a passphrase protected vault instead. Encrypted files
+/// are always readable given the passphrase, so turning encryption off again doesn't lose tokens.
+#[derive(Clone)]
+pub struct TokenStore {
+    dir: PathBuf,
+    encrypt: bool,
+}
+
+impl TokenStore {
+    pub fn open(config: &TokenConfig) -> Result<Self> {
+        let dir = crate::utils::config_dir()?.join("tokens");
+        fs::create_dir_all(&dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(Self { dir, encrypt: config.encrypt })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// Loads the tokens stored under `key`, migrating them from `legacy_file` if that's where they still are.
+    pub fn load(&self, key: &str, legacy_file: &str) -> Result<Option<StoredToken>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return self.migrate_legacy(key, legacy_file);
+        }
+
+        let (contents, encrypted) = self.read(&path)?;
+        match serde_json::from_str(&contents) {
+            Ok(token) => {
+                if self.encrypt && !encrypted {
+                    self.save(key, &token)?;
+                    log::info!("Moved {} tokens into the encrypted vault", key);
+                }
+                Ok(Some(token))
+            }
+            Err(e) => {
+                log::warn!("Ignoring unreadable token file {}: {}", path.display(), e);
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn save(&self, key: &str, token: &StoredToken) -> Result<()> {
+        self.write(&self.path(key), token)
+    }
+
+    /// Deletes the tokens stored under `key`, if any.
+    pub fn remove(&self, key: &str) -> Result<()> {
+        remove_if_exists(&self.path(key))
+    }
+
+    fn pending_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.pending.json", key))
+    }
+
+    pub fn load_pending(&self, key: &str) -> Result<Option<PendingLogin>> {
+        let path = self.pending_path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&self.read(&path)?.0)?))
+    }
+
+    /// The PKCE verifier is as sensitive as the tokens, so pending logins are stored the same way.
+    pub fn save_pending(&self, key: &str, pending: &PendingLogin) -> Result<()> {
+        self.write(&self.pending_path(key), pending)
+    }
+
+    pub fn remove_pending(&self, key: &str) -> Result<()> {
+        remove_if_exists(&self.pending_path(key))
+    }
+
+    /// Returns the file contents, decrypted if needed, and whether they were encrypted.
+    fn read(&self, path: &Path) -> Result<(String, bool)> {
+        let contents = fs::read_to_string(path)?;
+        if vault::is_encrypted(&contents) {
+            let plaintext = vault::decrypt(&contents, vault::passphrase()?)?;
+            Ok((String::from_utf8(plaintext).map_err(|e| Error::Decode(e.to_string()))?, true))
+        } else {
+            Ok((contents, false))
+        }
+    }
+
+    /// Writes to a temporary file first and renames it into place, so a crash never leaves a half-written file.
+    fn write<T: Serialize>(&self, path: &Path, value: &T) -> Result<()> {
+        let tmp_path = path.with_extension("json.tmp");
+        // The permissions only apply when the file is created, so never reuse a stale temporary file
+        let _ = fs::remove_file(&tmp_path);
+        let mut contents = serde_json::to_string_pretty(value)?;
+        if self.encrypt {
+            contents = vault::encrypt(contents.as_bytes(), vault::passphrase()?)?;
+        }
+        let mut file = create_private(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads the newline separated `access token / refresh token / expiry` files older versions
+    /// wrote to the working directory, and moves them into the store.
+    fn migrate_legacy(&self, key: &str, legacy_file: &str) -> Result<Option<StoredToken>> {
+        let contents = match fs::read_to_string(legacy_file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let lines: Vec<&str> = contents.split('\n').collect();
+        let token = match lines.as_slice() {
+            [access_token, refresh_token, expires_at] => StoredToken {
+                access_token: access_token.to_string(),
+                refresh_token: Some(refresh_token.to_string()).filter(|token| !token.is_empty()),
+                token_type: "bearer".to_string(),
+                scopes: Vec::new(),
+                // A malformed expiry is treated as expired so the token gets refreshed
+                expires_at: Some(expires_at.trim().parse().unwrap_or(0)),
+                obtained_at: now(),
+            },
+            _ => {
+                log::warn!("Ignoring malformed legacy token file {}", legacy_file);
+                return Ok(None);
+            }
+        };
+
+        self.save(key, &token)?;
+        fs::remove_file(legacy_file)?;
+        log::info!("Moved tokens from {} to {}", legacy_file, self.path(key).display());
+        Ok(Some(token))
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    OpenOptions::new().write(true).create(true).truncate(true).open(path)
+}
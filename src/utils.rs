@@ -1,3 +1,47 @@
-pub fn log_error<E: std::error::Error>(error: E) {
+use crate::error::{Error, Result};
+
+/// Prints the error, with a hint on what to do about it where there is one.
+pub fn log_error(error: &Error) {
     eprintln!("Error: {}", error);
+    let hint = match error {
+        Error::Auth(_) => Some("Run `auth` to log in again, `auth status` shows the stored logins"),
+        Error::RateLimited { .. } => Some("The service is limiting requests, try again later"),
+        Error::Config(_) => Some("Check the configuration file"),
+        Error::Request(_) => Some("Check the network connection"),
+        Error::PlanOutdated { .. } => Some("Run `plan` again to create a new plan"),
+        _ => None,
+    };
+    if let Some(hint) = hint {
+        eprintln!("{}", hint);
+    }
+}
+
+/// Formats a unix timestamp relative to now, e.g. "5m ago".
+pub fn format_age(timestamp: u64) -> String {
+    format!("{} ago", format_duration(crate::db::now().saturating_sub(timestamp)))
+}
+
+/// Formats a unix timestamp that may lie in the future, e.g. "in 5m" or "5m ago".
+pub fn format_relative(timestamp: u64) -> String {
+    let now = crate::db::now();
+    if timestamp > now {
+        format!("in {}", format_duration(timestamp - now))
+    } else {
+        format_age(timestamp)
+    }
+}
+
+fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// The per-user configuration directory of the app, e.g. `~/.config/tidal-spotify-sync` on Linux.
+pub fn config_dir() -> Result<std::path::PathBuf> {
+    let base = dirs::config_dir().ok_or_else(|| Error::Config("Could not determine the user configuration directory".to_string()))?;
+    Ok(base.join("tidal-spotify-sync"))
 }
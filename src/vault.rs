@@ -0,0 +1,92 @@
+use crate::error::{Error, Result};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// Environment variable holding the vault passphrase for unattended runs
+pub const PASSPHRASE_ENV: &str = "TIDAL_SPOTIFY_SYNC_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+
+/// Passphrase-encrypted contents of a file: AES-256-GCM with a key derived from the passphrase by Argon2id.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    version: u32,
+    kdf: String,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Whether `contents` were written by [`encrypt`].
+pub fn is_encrypted(contents: &str) -> bool {
+    serde_json::from_str::<Envelope>(contents).is_ok()
+}
+
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    argon2::password_hash::rand_core::RngCore::fill_bytes(&mut OsRng, &mut salt);
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext).map_err(|_| Error::Other("Failed to encrypt tokens".to_string()))?;
+
+    let envelope = Envelope {
+        version: 1,
+        kdf: "argon2id".to_string(),
+        salt: BASE64.encode(salt),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    };
+    Ok(serde_json::to_string_pretty(&envelope)?)
+}
+
+pub fn decrypt(contents: &str, passphrase: &str) -> Result<Vec<u8>> {
+    let envelope: Envelope = serde_json::from_str(contents)?;
+    if envelope.version != 1 || envelope.kdf != "argon2id" {
+        return Err(Error::Decode(format!("Unsupported token vault format (version {}, {})", envelope.version, envelope.kdf)));
+    }
+
+    let salt = decode_base64(&envelope.salt)?;
+    let nonce = decode_base64(&envelope.nonce)?;
+    if nonce.len() != 12 {
+        return Err(Error::Decode("Corrupt token vault: invalid nonce".to_string()));
+    }
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), decode_base64(&envelope.ciphertext)?.as_slice())
+        .map_err(|_| Error::Auth("Could not decrypt tokens, wrong passphrase?".to_string()))?;
+    Ok(plaintext)
+}
+
+/// The vault passphrase from the environment, or asked for once per run.
+pub fn passphrase() -> Result<&'static str> {
+    static PASSPHRASE: OnceLock<String> = OnceLock::new();
+    if let Some(passphrase) = PASSPHRASE.get() {
+        return Ok(passphrase);
+    }
+
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => rpassword::prompt_password("Token vault passphrase: ")?,
+    };
+    if passphrase.is_empty() {
+        return Err(Error::Auth("The token vault passphrase can't be empty".to_string()));
+    }
+    Ok(PASSPHRASE.get_or_init(|| passphrase))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key<Aes256Gcm>> {
+    let mut key = Key::<Aes256Gcm>::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::Other(format!("Failed to derive the vault key: {}", e)))?;
+    Ok(key)
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    BASE64.decode(encoded).map_err(|e| Error::Decode(format!("Corrupt token vault: {}", e)))
+}